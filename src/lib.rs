@@ -0,0 +1,5921 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub const MINIMUM_LIQUIDITY: u64 = 1000;
+
+// Number of past update_price values kept in price_history.
+pub const PRICE_HISTORY_CAPACITY: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RoundingMode {
+    #[default]
+    Floor,
+    Ceil,
+    Nearest,
+}
+
+// How add_liquidity and swap handle a reserve that would pass u64::MAX. remove_liquidity only
+// subtracts from the reserves, so it behaves the same under both.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    Checked,
+    Saturating,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepositTiming {
+    Now,
+    AfterSwap,
+}
+
+// A pool operation as data, for replaying recorded events through LpPool::apply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    AddLiquidity(u64),
+    RemoveLiquidity(u64),
+    Swap(u64),
+}
+
+// Output of the method a Command dispatched to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CommandResult {
+    AddLiquidity(u64),
+    RemoveLiquidity(u64, u64),
+    Swap(u64),
+}
+
+// Successful operations processed by a pool.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Stats {
+    pub adds: u64,
+    pub removes: u64,
+    pub swaps: u64,
+}
+
+// Dashboard figures gathered by LpPool::pool_metrics. solvent stands in for a health band: it is
+// the only health check the pool has.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolMetrics {
+    pub tvl: u128,
+    pub lp_token_price: Option<f64>,
+    pub fee_bps: u64,
+    pub reserve_ratio: u64,
+    pub solvent: bool,
+    pub swap_count: u64,
+    pub total_volume: u64,
+    pub accrued_fees: u64,
+}
+
+// Result of a pool operation tagged with the sequence number it was assigned; None when it
+// changed nothing, e.g. in dry-run mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Receipt<T> {
+    pub sequence: Option<u64>,
+    pub result: T,
+}
+
+// External source of the staked token price in tokens.
+pub trait PriceOracle {
+    fn price(&self) -> u64;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedPrice(pub u64);
+
+impl PriceOracle for FixedPrice {
+    fn price(&self) -> u64 {
+        self.0
+    }
+}
+
+// Destination of the JSON operation log. Clones share the writer so a transaction rollback
+// keeps it; it is not pool state, so it never affects equality.
+#[cfg(feature = "log")]
+#[derive(Clone)]
+struct LogWriter(Arc<std::sync::Mutex<Box<dyn std::io::Write + Send>>>);
+
+#[cfg(feature = "log")]
+impl core::fmt::Debug for LogWriter {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "LogWriter")
+    }
+}
+
+#[cfg(feature = "log")]
+impl PartialEq for LogWriter {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+// Shared so the pool stays Clone (transaction snapshots keep the same oracle); two pools are
+// only equal if they hold the very same oracle.
+#[derive(Clone)]
+struct SharedOracle(Arc<dyn PriceOracle + Send + Sync>);
+
+impl core::fmt::Debug for SharedOracle {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "SharedOracle({})", self.0.price())
+    }
+}
+
+impl PartialEq for SharedOracle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// Shared and compared like SharedOracle.
+#[derive(Clone)]
+struct SwapGuard(Arc<dyn Fn(u64) -> bool + Send + Sync>);
+
+impl core::fmt::Debug for SwapGuard {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "SwapGuard")
+    }
+}
+
+impl PartialEq for SwapGuard {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolConfig {
+    pub price: u64,
+    pub fee_min: u64,
+    pub fee_max: u64,
+    pub liquidity_target: u64,
+    // Percent of each withdrawal kept in the reserves; zero disables it.
+    pub withdraw_fee: u64,
+    // Rounding of LP minting and withdrawal amounts.
+    pub rounding: RoundingMode,
+    // Ceiling on lp_token_supply enforced by deposits; None leaves it uncapped.
+    pub max_lp_supply: Option<u64>,
+    // token_reserve a swap may not go below, even when the reserve could cover it.
+    pub reserve_floor: u64,
+    // Smallest staked_token_amount swap accepts; None accepts any non-zero amount.
+    pub min_swap_amount: Option<u64>,
+    // Percent of each swap fee paid out of the reserves to the protocol treasury; the rest stays
+    // in token_reserve for LPs. Defaults to 100, the whole fee.
+    pub protocol_fee_share: u64,
+    // Decimal places of the token amounts, only used by to_display and from_display.
+    pub decimals: u8,
+    // Saturating makes add_liquidity and swap behave like their _saturating variants.
+    pub overflow_policy: OverflowPolicy,
+}
+
+#[derive(Debug, Clone, PartialEq)] 
+pub struct LpPool {
+    config: PoolConfig,
+    token_reserve: u64,
+    staked_token_reserve: u64,
+    lp_token_supply: u64,
+    locked_lp_supply: u64,
+    total_fees: u64,
+    total_gross_swapped: u64,
+    collected_fees: u64,
+    treasury_balance: u64,
+    price_oracle: Option<SharedOracle>,
+    swap_guard: Option<SwapGuard>,
+    stats: Stats,
+    sequence: u64,
+    dry_run: bool,
+    paused: bool,
+    price_history: VecDeque<u64>,
+    #[cfg(feature = "std")]
+    last_price_update: Option<std::time::Instant>,
+    #[cfg(feature = "log")]
+    log_writer: Option<LogWriter>,
+    // Records of operations inside an open transaction, written once it succeeds.
+    #[cfg(feature = "log")]
+    pending_log: Option<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Errors {
+    PropertyMustBeGreaterThanZero,
+    FeeMaxMustBeGreaterThanFeeMin,
+    InsufficientLiquidity,
+    PriceChangeTooLarge,
+    InconsistentState,
+    Overflow,
+    FeeTooLarge,
+    ParseError,
+    ImbalancedDeposit,
+    PercentOutOfRange,
+    SupplyCapExceeded,
+    FeeTooHigh,
+    IncompatiblePools,
+    NotProfitable,
+    FeeOutOfRange,
+    SwapTooSmall,
+    ReserveFloorBreached,
+    StalePrice,
+    WithdrawalRoundsToZero,
+    InvalidParameter { field: &'static str, reason: &'static str },
+    SwapYieldsNothing,
+    ReplayFailed { index: usize, error: Box<Errors> },
+    SwapRejectedByPolicy,
+    PoolPaused,
+    UnfairWithdrawal,
+    WouldBecomeInsolvent,
+    RoundingLossTooHigh,
+    DepositMintsNothing,
+    PoolPoisoned,
+    NoFeeAccrual,
+}
+
+impl core::fmt::Display for Errors {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Errors::PropertyMustBeGreaterThanZero => write!(f, "Property must be greater than zero"),
+            Errors::FeeMaxMustBeGreaterThanFeeMin => write!(f, "Fee max must be greater than fee min"),
+            Errors::InsufficientLiquidity => write!(f, "Insufficient liquidity"),
+            Errors::PriceChangeTooLarge => write!(f, "Price change too large"),
+            Errors::InconsistentState => write!(f, "Inconsistent pool state"),
+            Errors::Overflow => write!(f, "Arithmetic overflow"),
+            Errors::FeeTooLarge => write!(f, "Fee too large"),
+            Errors::ParseError => write!(f, "Parse error"),
+            Errors::ImbalancedDeposit => write!(f, "Deposit does not match the reserve ratio"),
+            Errors::PercentOutOfRange => write!(f, "Percent must be at most 100"),
+            Errors::SupplyCapExceeded => write!(f, "LP token supply cap exceeded"),
+            Errors::FeeTooHigh => write!(f, "Fee above the accepted maximum"),
+            Errors::IncompatiblePools => write!(f, "Pools have different parameters"),
+            Errors::NotProfitable => write!(f, "No profitable swap size"),
+            Errors::FeeOutOfRange => write!(f, "Fee must be at most 100 percent"),
+            Errors::SwapTooSmall => write!(f, "Swap below the minimum amount"),
+            Errors::ReserveFloorBreached => write!(f, "Swap would leave the token reserve below its floor"),
+            Errors::StalePrice => write!(f, "Price is stale"),
+            Errors::WithdrawalRoundsToZero => write!(f, "Withdrawal rounds to zero tokens"),
+            Errors::InvalidParameter { field, reason } => write!(f, "Invalid parameter: {} {}", field, reason),
+            Errors::SwapYieldsNothing => write!(f, "Swap output is zero after fees"),
+            Errors::ReplayFailed { index, error } => write!(f, "Replay failed at command {}: {}", index, error),
+            Errors::SwapRejectedByPolicy => write!(f, "Swap rejected by policy"),
+            Errors::PoolPaused => write!(f, "Pool is paused"),
+            Errors::UnfairWithdrawal => write!(f, "Withdrawal exceeds the burned share of the reserves"),
+            Errors::WouldBecomeInsolvent => write!(f, "Price change would leave the pool insolvent"),
+            Errors::RoundingLossTooHigh => write!(f, "Round trip loses more than rounding explains"),
+            Errors::DepositMintsNothing => write!(f, "Deposit mints no LP tokens"),
+            Errors::PoolPoisoned => write!(f, "Pool lock poisoned by a panic"),
+            Errors::NoFeeAccrual => write!(f, "Swap fees accrue nothing to LPs"),
+        }
+    }
+}
+
+impl core::error::Error for Errors {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Errors::ReplayFailed { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<core::num::ParseIntError> for Errors {
+    fn from(_: core::num::ParseIntError) -> Self {
+        Errors::ParseError
+    }
+}
+
+// Builds a pool from the four init parameters given as decimal strings:
+// price, fee_min, fee_max, liquidity_target.
+pub fn parse_pool(args: &[&str]) -> Result<LpPool, Errors> {
+    let [price, fee_min, fee_max, liquidity_target] = args else {
+        return Err(Errors::ParseError);
+    };
+
+    LpPool::init(PoolConfig::new(price.parse()?, fee_min.parse()?, fee_max.parse()?, liquidity_target.parse()?))
+}
+
+// Relative cost of applying command, for deciding how many to batch: the number of wide
+// multiplications it takes. A deposit mints with one; a withdrawal prices both reserves and takes
+// the withdraw fee from each; a swap prices its input, reads the fee curve (utilization, then the
+// interpolation), charges the fee and splits off the protocol share. Amounts don't change the weight.
+pub fn operation_weight(command: &Command) -> u32 {
+    match command {
+        Command::AddLiquidity(_) => 1,
+        Command::RemoveLiquidity(_) => 4,
+        Command::Swap(_) => 5,
+    }
+}
+
+// Blended per-LP entry value of a position built from several deposits, each given as
+// (lp_minted, lp_value_at_deposit) and weighted by the LP tokens it minted. 0.0 without any.
+pub fn weighted_entry_value(positions: &[(u64, u64)]) -> f64 {
+    let lp_minted: u128 = positions.iter().map(|&(lp_minted, _)| lp_minted as u128).sum();
+    if lp_minted == 0 {
+        return 0.0;
+    }
+
+    let cost: u128 = positions.iter().map(|&(lp_minted, lp_value)| lp_minted as u128 * lp_value as u128).sum();
+    cost as f64 / lp_minted as f64
+}
+
+// Values the low end of an accepted range is searched over one by one before giving up.
+const LOW_END_SCAN: u64 = 64;
+
+// (smallest, largest) value in [low, high] that accepts takes, assuming the accepted values form
+// one range. The start of that range is scanned for among the first LOW_END_SCAN values (small
+// swaps can be rejected because their fee rounds up to the whole output) and its end is
+// bisected; None when nothing in the scanned values is accepted.
+fn accepted_range(low: u64, high: u64, accepts: impl Fn(u64) -> bool) -> Option<(u64, u64)> {
+    let smallest = (low..=high.min(low.saturating_add(LOW_END_SCAN - 1))).find(|&value| accepts(value))?;
+    if accepts(high) {
+        return Some((smallest, high));
+    }
+
+    let (mut accepted, mut rejected) = (smallest, high);
+    while rejected - accepted > 1 {
+        let middle = accepted + (rejected - accepted) / 2;
+        if accepts(middle) {
+            accepted = middle;
+        } else {
+            rejected = middle;
+        }
+    }
+    Some((smallest, accepted))
+}
+
+// a * b / c rounded down, for callers that guarantee a <= c (or b <= c) so the result fits in
+// u64. Anything else goes through mul_div.
+fn mul_div_floor(a: u64, b: u64, c: u64) -> u64 {
+    debug_assert!(a <= c || b <= c);
+    (a as u128 * b as u128 / c as u128) as u64
+}
+
+// a * b / c in u128, failing with Overflow when the result doesn't fit in u64.
+fn mul_div(a: u64, b: u64, c: u64, rounding: RoundingMode) -> Result<u64, Errors> {
+    let numerator = a as u128 * b as u128;
+    let denominator = c as u128;
+
+    let result = match rounding {
+        RoundingMode::Floor => numerator / denominator,
+        RoundingMode::Ceil => numerator.div_ceil(denominator),
+        RoundingMode::Nearest => (numerator + denominator / 2) / denominator,
+    };
+    u64::try_from(result).map_err(|_| Errors::Overflow)
+}
+
+// Capped at token_amount so a fee percentage above 100 can't push the net output below zero.
+fn fee_amount(token_amount: u64, fee_percentage: u64) -> u64 {
+    ((token_amount as u128 * fee_percentage as u128) / 100).min(token_amount as u128) as u64
+}
+
+// A proportional withdrawal leaves each reserve within two units of its exact share: one from
+// rounding the withdrawn amount, one from truncating the withdraw fee. With t' = t*k + e1 and
+// s' = s*k + e2, the cross products then differ by |e1*s - e2*t| <= 2 * (t + s).
+fn ratio_drift_within_rounding(token_before: u64, staked_before: u64, token_after: u64, staked_after: u64) -> bool {
+    let after = token_after as u128 * staked_before as u128;
+    let before = token_before as u128 * staked_after as u128;
+    after.abs_diff(before) <= 2 * (token_before as u128 + staked_before as u128)
+}
+
+// Swap fees round to the nearest unit instead of truncating, which systematically undercharged;
+// withdraw fees keep fee_amount so lp_tokens_for_token_amount stays its exact inverse.
+fn swap_fee_amount(token_amount: u64, fee_percentage: u64) -> u64 {
+    ((token_amount as u128 * fee_percentage as u128 + 50) / 100).min(token_amount as u128) as u64
+}
+
+// swap_fee_amount for a fee given in basis points.
+fn swap_fee_amount_bps(token_amount: u64, fee_bps: u64) -> u64 {
+    ((token_amount as u128 * fee_bps as u128 + 5_000) / 10_000).min(token_amount as u128) as u64
+}
+
+// 10^decimals without powi, which core doesn't provide.
+fn decimal_scale(decimals: u8) -> f64 {
+    (0..decimals).fold(1.0, |scale, _| scale * 10.0)
+}
+
+// Rounded up so a swap never reports less slippage than it realizes.
+fn slippage_bps_of(token_amount: u64, fee: u64) -> u64 {
+    (fee as u128 * 10_000).div_ceil(token_amount as u128) as u64
+}
+
+impl PoolConfig {
+
+    pub fn new(price: u64, fee_min: u64, fee_max: u64, liquidity_target: u64) -> Self {
+        PoolConfig {
+            price,
+            fee_min,
+            fee_max,
+            liquidity_target,
+            withdraw_fee: 0,
+            rounding: RoundingMode::Floor,
+            max_lp_supply: None,
+            reserve_floor: 0,
+            min_swap_amount: None,
+            protocol_fee_share: 100,
+            decimals: 0,
+            overflow_policy: OverflowPolicy::Checked,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Errors> {
+        let required = [
+            ("price", self.price),
+            ("fee_min", self.fee_min),
+            ("fee_max", self.fee_max),
+            ("liquidity_target", self.liquidity_target),
+        ];
+        if let Some(&(field, _)) = required.iter().find(|&&(_, value)| value == 0) {
+            return Err(Errors::InvalidParameter { field, reason: "must be greater than zero" });
+        }
+
+        if self.fee_min >= self.fee_max {
+            return Err(Errors::FeeMaxMustBeGreaterThanFeeMin);
+        }
+
+        if self.fee_max > 100 {
+            return Err(Errors::FeeOutOfRange);
+        }
+
+        if self.withdraw_fee > 100 || self.protocol_fee_share > 100 {
+            return Err(Errors::FeeTooLarge);
+        }
+
+        Ok(())
+    }
+}
+
+impl LpPool {
+
+    pub fn init(config: PoolConfig) -> Result<Self, Errors> {
+        config.validate()?;
+
+        Ok(LpPool {
+            config,
+            token_reserve: 0,
+            staked_token_reserve: 0,
+            lp_token_supply: 0,
+            locked_lp_supply: 0,
+            total_fees: 0,
+            total_gross_swapped: 0,
+            collected_fees: 0,
+            treasury_balance: 0,
+            price_oracle: None,
+            swap_guard: None,
+            stats: Stats::default(),
+            sequence: 0,
+            dry_run: false,
+            paused: false,
+            price_history: VecDeque::new(),
+            #[cfg(feature = "std")]
+            last_price_update: None,
+            #[cfg(feature = "log")]
+            log_writer: None,
+            #[cfg(feature = "log")]
+            pending_log: None,
+        })
+
+    }
+
+    // A pool charging a withdraw fee locks MINIMUM_LIQUIDITY of lp_token_supply (all of it when
+    // there is less), as a first deposit does: the fee paid on the last exit stays in the reserves
+    // and needs LP tokens left to back it.
+    pub fn init_with_reserves(config: PoolConfig, token_reserve: u64, staked_token_reserve: u64, lp_token_supply: u64) -> Result<Self, Errors> {
+        let mut lp_pool = LpPool::init(config)?;
+
+        if lp_token_supply == 0 && (token_reserve != 0 || staked_token_reserve != 0) {
+            return Err(Errors::InconsistentState);
+        }
+
+        lp_pool.token_reserve = token_reserve;
+        lp_pool.staked_token_reserve = staked_token_reserve;
+        lp_pool.lp_token_supply = lp_token_supply;
+        if config.withdraw_fee > 0 {
+            lp_pool.locked_lp_supply = lp_token_supply.min(MINIMUM_LIQUIDITY);
+        }
+        Ok(lp_pool)
+    }
+
+    pub fn config(&self) -> &PoolConfig {
+        &self.config
+    }
+
+    // Token value backing one LP token.
+    pub fn lp_token_price(&self) -> Option<f64> {
+        if self.lp_token_supply == 0 {
+            return None;
+        }
+
+        Some(self.total_value_locked() as f64 / self.lp_token_supply as f64)
+    }
+
+    // lp_token_price expressed in staked tokens at config.price.
+    pub fn lp_value_in_staked(&self) -> Option<f64> {
+        self.lp_token_price().map(|lp_token_price| lp_token_price / self.config.price as f64)
+    }
+
+    // LP tokens per token of token_reserve, the inverse of an LP token's token side.
+    pub fn mint_rate(&self) -> Option<f64> {
+        if self.token_reserve == 0 {
+            return None;
+        }
+
+        Some(self.lp_token_supply as f64 / self.token_reserve as f64)
+    }
+
+    // Percentage of lp_token_supply that lp_token_amount represents.
+    pub fn lp_share_percent(&self, lp_token_amount: u64) -> Option<f64> {
+        if self.lp_token_supply == 0 {
+            return None;
+        }
+
+        Some(lp_token_amount as f64 * 100.0 / self.lp_token_supply as f64)
+    }
+
+    // Basis-point move of lp_token_price for a price_delta_bps rise of the configured price.
+    // Only the staked side revalues, so the answer scales with its share of the pool value.
+    // Fails with Overflow if the raised price or the move doesn't fit.
+    pub fn lp_price_sensitivity(&self, price_delta_bps: u64) -> Result<i64, Errors> {
+        let old_value = self.total_value_locked();
+        if self.lp_token_supply == 0 || old_value == 0 {
+            return Ok(0);
+        }
+
+        let mut repriced = self.simulation();
+        let price_factor = price_delta_bps.checked_add(10_000).ok_or(Errors::Overflow)?;
+        repriced.config.price = mul_div(self.config.price, price_factor, 10_000, RoundingMode::Floor)?;
+
+        let value_change = (repriced.total_value_locked() - old_value).checked_mul(10_000).ok_or(Errors::Overflow)?;
+        i64::try_from(value_change / old_value).map_err(|_| Errors::Overflow)
+    }
+
+    // Cheap self-consistency check for fuzzing: the config must still be valid and LP tokens
+    // must exist exactly when there are reserves backing them.
+    pub fn check_invariants(&self) -> Result<(), Errors> {
+        self.config.validate()?;
+
+        let has_reserves = self.token_reserve != 0 || self.staked_token_reserve != 0;
+        if has_reserves != (self.lp_token_supply != 0) {
+            return Err(Errors::InconsistentState);
+        }
+
+        Ok(())
+    }
+
+    // Value of both reserves in token terms; u128 so that staked_token_reserve * price can't overflow.
+    pub fn total_value_locked(&self) -> u128 {
+        self.token_reserve as u128 + self.staked_token_reserve as u128 * self.config.price as u128
+    }
+
+    // Staked tokens worth token_amount at config.price, rounded down. Reserves and fees are
+    // ignored.
+    pub fn token_to_staked(&self, token_amount: u64) -> u64 {
+        token_amount / self.config.price
+    }
+
+    // Tokens worth staked_amount at config.price. Reserves and fees are ignored.
+    pub fn staked_to_token(&self, staked_amount: u64) -> Result<u64, Errors> {
+        staked_amount.checked_mul(self.config.price).ok_or(Errors::Overflow)
+    }
+
+    // Raw base units to a human-readable amount using config.decimals.
+    pub fn to_display(&self, raw: u64) -> f64 {
+        raw as f64 / decimal_scale(self.config.decimals)
+    }
+
+    // Inverse of to_display, rounded to the nearest base unit; negative values give 0.
+    pub fn from_display(&self, value: f64) -> u64 {
+        (value * decimal_scale(self.config.decimals) + 0.5) as u64
+    }
+
+    // (field, self value, other value) for every field that differs, for readable test failures.
+    // Flags compare as 0 or 1 and modes as their variant index; an unset max_lp_supply reads
+    // u64::MAX and an unset min_swap_amount 0. price_history is compared position by position, a
+    // missing entry reading 0. The oracle, swap guard, price update time and log writer aren't
+    // compared.
+    pub fn diff(&self, other: &LpPool) -> Vec<(&'static str, u64, u64)> {
+        let mut diff: Vec<_> = self
+            .state_fields()
+            .into_iter()
+            .zip(other.state_fields())
+            .filter(|((_, a), (_, b))| a != b)
+            .map(|((name, a), (_, b))| (name, a, b))
+            .collect();
+
+        let history_len = self.price_history.len().max(other.price_history.len());
+        for index in 0..history_len {
+            let a = self.price_history.get(index).copied().unwrap_or(0);
+            let b = other.price_history.get(index).copied().unwrap_or(0);
+            if a != b {
+                diff.push(("price_history", a, b));
+            }
+        }
+        diff
+    }
+
+    fn state_fields(&self) -> Vec<(&'static str, u64)> {
+        let config = &self.config;
+        let mut fields = self.numeric_fields().to_vec();
+        fields.extend([
+            ("rounding", config.rounding as u64),
+            ("max_lp_supply", config.max_lp_supply.unwrap_or(u64::MAX)),
+            ("reserve_floor", config.reserve_floor),
+            ("min_swap_amount", config.min_swap_amount.unwrap_or(0)),
+            ("overflow_policy", config.overflow_policy as u64),
+            ("sequence", self.sequence),
+            ("dry_run", self.dry_run as u64),
+            ("paused", self.paused as u64),
+        ]);
+        fields
+    }
+
+    // The typed counterpart of metrics, with the figures a dashboard shows instead of every field.
+    pub fn pool_metrics(&self) -> PoolMetrics {
+        PoolMetrics {
+            tvl: self.total_value_locked(),
+            lp_token_price: self.lp_token_price(),
+            fee_bps: self.current_fee_bps(),
+            reserve_ratio: self.utilization(),
+            solvent: self.is_solvent(),
+            swap_count: self.stats.swaps,
+            total_volume: self.total_volume(),
+            accrued_fees: self.collected_fees(),
+        }
+    }
+
+    // Every numeric field as named pairs, plus the sequence counter and the live fee_percentage,
+    // utilization and tvl, for metrics systems that scrape key-value pairs. tvl is clamped to
+    // u64::MAX.
+    pub fn metrics(&self) -> Vec<(&'static str, u64)> {
+        let mut metrics = self.numeric_fields().to_vec();
+        metrics.extend([
+            ("reserve_floor", self.config.reserve_floor),
+            ("sequence", self.sequence),
+            ("fee_percentage", self.calculate_fee_percentage()),
+            ("utilization", self.utilization()),
+            ("tvl", self.total_value_locked().min(u64::MAX as u128) as u64),
+        ]);
+        metrics
+    }
+
+    fn numeric_fields(&self) -> [(&'static str, u64); 18] {
+        let config = &self.config;
+        [
+            ("price", config.price),
+            ("fee_min", config.fee_min),
+            ("fee_max", config.fee_max),
+            ("liquidity_target", config.liquidity_target),
+            ("withdraw_fee", config.withdraw_fee),
+            ("protocol_fee_share", config.protocol_fee_share),
+            ("decimals", config.decimals as u64),
+            ("token_reserve", self.token_reserve),
+            ("staked_token_reserve", self.staked_token_reserve),
+            ("lp_token_supply", self.lp_token_supply),
+            ("locked_lp_supply", self.locked_lp_supply),
+            ("total_fees", self.total_fees),
+            ("total_gross_swapped", self.total_gross_swapped),
+            ("collected_fees", self.collected_fees),
+            ("treasury_balance", self.treasury_balance),
+            ("adds", self.stats.adds),
+            ("removes", self.stats.removes),
+            ("swaps", self.stats.swaps),
+        ]
+    }
+
+    // Orders pools by total_value_locked, e.g. for ranking.
+    pub fn value_cmp(&self, other: &LpPool) -> core::cmp::Ordering {
+        self.total_value_locked().cmp(&other.total_value_locked())
+    }
+
+    // Largest LP amount whose withdrawal leaves the pool solvent. Floor rounding never takes more
+    // than an LP token's share, so a solvent pool can release its whole withdrawable supply; with
+    // rounding up, solvency isn't monotonic in the amount, so this bisects for a boundary where
+    // one more LP token would break solvency. An insolvent pool gives 0.
+    pub fn max_removable_lp(&self) -> u64 {
+        let solvent_after = |lp_token_amount: u64| {
+            if lp_token_amount == 0 {
+                return self.is_solvent();
+            }
+
+            let mut simulated = self.simulation();
+            simulated.remove_liquidity(lp_token_amount).is_ok() && simulated.is_solvent()
+        };
+
+        let withdrawable = self.lp_token_supply - self.locked_lp_supply;
+        if !solvent_after(0) {
+            return 0;
+        }
+        if solvent_after(withdrawable) {
+            return withdrawable;
+        }
+
+        let (mut solvent, mut insolvent) = (0, withdrawable);
+        while insolvent - solvent > 1 {
+            let middle = solvent + (insolvent - solvent) / 2;
+            if solvent_after(middle) {
+                solvent = middle;
+            } else {
+                insolvent = middle;
+            }
+        }
+        solvent
+    }
+
+    // Largest staked_token_amount swap currently accepts, or 0 if it accepts none.
+    pub fn max_swap_in(&self) -> u64 {
+        self.swap_range().map_or(0, |(_, largest)| largest)
+    }
+
+    fn swap_range(&self) -> Option<(u64, u64)> {
+        let price = self.swap_price().ok()?;
+        accepted_range(self.min_swap_in(), self.token_reserve / price, |amount| self.can_swap(amount))
+    }
+
+    fn min_swap_in(&self) -> u64 {
+        self.config.min_swap_amount.unwrap_or(1).max(1)
+    }
+
+    // (smallest, largest) staked_token_amount swap accepts right now. When no swap fits the
+    // smallest is the configured minimum and the largest is 0.
+    pub fn swap_bounds(&self) -> (u64, u64) {
+        self.swap_range().unwrap_or((self.min_swap_in(), 0))
+    }
+
+    // (smallest, largest) amount add_liquidity accepts right now. A first deposit has to exceed
+    // MINIMUM_LIQUIDITY and a later one has to mint at least one LP token; the largest is bounded
+    // by max_lp_supply if set and by u64::MAX otherwise, and is 0 when no deposit fits.
+    pub fn add_bounds(&self) -> (u64, u64) {
+        let min_deposit = if self.lp_token_supply == 0 { MINIMUM_LIQUIDITY + 1 } else { self.smallest_minting_deposit() };
+        let accepts = |amount| self.simulation().add_liquidity(amount).is_ok();
+        let max_deposit = accepted_range(min_deposit, u64::MAX - self.token_reserve, accepts).map_or(0, |(_, largest)| largest);
+        (min_deposit, max_deposit)
+    }
+
+    // Depositing the whole token reserve mints the whole supply, so the smallest deposit that
+    // mints anything is at most token_reserve and is bisected below it.
+    fn smallest_minting_deposit(&self) -> u64 {
+        if self.token_reserve == 0 {
+            return 1;
+        }
+
+        let (mut rejected, mut accepted) = (0, self.token_reserve);
+        while accepted - rejected > 1 {
+            let middle = rejected + (accepted - rejected) / 2;
+            if mul_div(middle, self.lp_token_supply, self.token_reserve, self.config.rounding).is_ok_and(|minted| minted > 0) {
+                accepted = middle;
+            } else {
+                rejected = middle;
+            }
+        }
+        accepted
+    }
+
+    // (token share, staked-token share) of total_value_locked in percent. The token share is
+    // rounded down and the staked share takes the rest, so a funded pool sums to 100.
+    pub fn composition(&self) -> (u64, u64) {
+        let total_value_locked = self.total_value_locked();
+        if total_value_locked == 0 {
+            return (0, 0);
+        }
+
+        let token_share = (self.token_reserve as u128 * 100 / total_value_locked) as u64;
+        (token_share, 100 - token_share)
+    }
+
+    // Lowest config.price at which is_solvent holds, never below 1. u64::MAX when no price can
+    // help, i.e. the token reserve falls short and there is no staked reserve to revalue.
+    pub fn min_safe_price(&self) -> u64 {
+        let shortfall = (self.lp_token_supply as u128).saturating_sub(self.token_reserve as u128);
+        if shortfall == 0 {
+            return 1;
+        }
+        if self.staked_token_reserve == 0 {
+            return u64::MAX;
+        }
+
+        let price = shortfall.div_ceil(self.staked_token_reserve as u128);
+        price.clamp(1, u64::MAX as u128) as u64
+    }
+
+    // Converts between the reserves at config.price, without a fee, until the token side is as
+    // close to target_token_percent of total_value_locked as whole staked tokens allow. Returns
+    // the staked tokens moved. Total value is unchanged; a target that would take the token
+    // reserve below reserve_floor is rejected.
+    pub fn rebalance_to_ratio(&mut self, target_token_percent: u64) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.rebalance_to_ratio(target_token_percent));
+        }
+        self.ensure_not_paused()?;
+
+        if target_token_percent > 100 {
+            return Err(Errors::PercentOutOfRange);
+        }
+
+        let total_value_locked = self.total_value_locked();
+        if total_value_locked == 0 {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        let price = self.config.price as u128;
+        let target_tokens = total_value_locked.checked_mul(target_token_percent as u128).ok_or(Errors::Overflow)? / 100;
+        let token_reserve = self.token_reserve as u128;
+        let (token_reserve, staked_token_reserve, staked_moved) = if target_tokens >= token_reserve {
+            let staked_moved = ((target_tokens - token_reserve) / price) as u64;
+            let token_reserve = u64::try_from(token_reserve + staked_moved as u128 * price).map_err(|_| Errors::Overflow)?;
+            (token_reserve, self.staked_token_reserve - staked_moved, staked_moved)
+        } else {
+            let staked_moved = ((token_reserve - target_tokens) / price) as u64;
+            let token_reserve = (token_reserve - staked_moved as u128 * price) as u64;
+            if token_reserve < self.config.reserve_floor {
+                return Err(Errors::ReserveFloorBreached);
+            }
+            let staked_token_reserve = self.staked_token_reserve.checked_add(staked_moved).ok_or(Errors::Overflow)?;
+            (token_reserve, staked_token_reserve, staked_moved)
+        };
+
+        self.token_reserve = token_reserve;
+        self.staked_token_reserve = staked_token_reserve;
+        self.commit("rebalance", &[("target_token_percent", target_token_percent), ("staked_moved", staked_moved)]);
+        Ok(staked_moved)
+    }
+
+    // token_reserve * staked_token_reserve, for comparison with a constant-product pool; None
+    // while either reserve is empty. Swaps here don't hold it constant.
+    pub fn product_invariant(&self) -> Option<u128> {
+        if self.token_reserve == 0 || self.staked_token_reserve == 0 {
+            return None;
+        }
+
+        Some(self.token_reserve as u128 * self.staked_token_reserve as u128)
+    }
+
+    // The reserves are worth at least one token per outstanding LP token.
+    pub fn is_solvent(&self) -> bool {
+        self.total_value_locked() >= self.lp_token_supply as u128
+    }
+
+    // A single update may at most double or halve the current price.
+    pub fn update_price(&mut self, new_price: u64) -> Result<(), Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.update_price(new_price));
+        }
+
+        if new_price == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        let current_price = self.config.price;
+        if new_price > current_price.saturating_mul(2) || new_price < current_price.div_ceil(2) {
+            return Err(Errors::PriceChangeTooLarge);
+        }
+
+        self.config.price = new_price;
+        if self.price_history.len() == PRICE_HISTORY_CAPACITY {
+            self.price_history.pop_front();
+        }
+        self.price_history.push_back(new_price);
+        #[cfg(feature = "std")]
+        {
+            self.last_price_update = Some(std::time::Instant::now());
+        }
+        self.commit("update_price", &[("price", new_price)]);
+        Ok(())
+    }
+
+    // update_price that refuses, leaving the price as it was, any price at which is_solvent would
+    // fail. total_value_locked is derived from the price, so there's no other bookkeeping to
+    // adjust.
+    pub fn update_price_and_rebalance(&mut self, new_price: u64) -> Result<(), Errors> {
+        if new_price != 0 && new_price < self.min_safe_price() {
+            return Err(Errors::WouldBecomeInsolvent);
+        }
+
+        self.update_price(new_price)
+    }
+
+    // config.price, provided update_price set it within max_age. The price given to init has no
+    // known age and counts as stale.
+    #[cfg(feature = "std")]
+    pub fn price_checked(&self, max_age: core::time::Duration) -> Result<u64, Errors> {
+        self.price_checked_at(std::time::Instant::now(), max_age)
+    }
+
+    // price_checked against an injected clock.
+    #[cfg(feature = "std")]
+    pub fn price_checked_at(&self, now: std::time::Instant, max_age: core::time::Duration) -> Result<u64, Errors> {
+        match self.last_price_update {
+            Some(updated_at) if now.saturating_duration_since(updated_at) <= max_age => Ok(self.config.price),
+            _ => Err(Errors::StalePrice),
+        }
+    }
+
+    // The last PRICE_HISTORY_CAPACITY prices set by update_price, oldest first.
+    pub fn price_history(&self) -> &VecDeque<u64> {
+        &self.price_history
+    }
+
+    // Simple average of the last window recorded prices; updates carry no timestamps to weight
+    // by. None when fewer than window prices (or none) were recorded.
+    pub fn twap(&self, window: usize) -> Option<u64> {
+        if window == 0 || window > self.price_history.len() {
+            return None;
+        }
+
+        let sum: u128 = self.price_history.iter().rev().take(window).map(|&price| price as u128).sum();
+        Some((sum / window as u128) as u64)
+    }
+
+    // Health figure from 0 to 100 for dashboards, weighted as:
+    // - 50 points for the token reserve's progress towards liquidity_target;
+    // - 20 for fee headroom, how far the fee sits above fee_min as a share of the band;
+    // - 30 for how close the swap price stays to the twap of the whole price history, all of
+    //   them lost at a divergence of 50% or more. A pool without history hasn't drifted, one
+    //   whose oracle returns 0 gets none.
+    pub fn resilience_score(&self) -> u64 {
+        let reserve_points = self.utilization().min(100) * 50 / 100;
+
+        let fee_band = self.config.fee_max - self.config.fee_min;
+        let fee_points = (self.calculate_fee_percentage() - self.config.fee_min) * 20 / fee_band;
+
+        let divergence_points = match (self.swap_price(), self.twap(self.price_history.len())) {
+            (Err(_), _) => 0,
+            (Ok(_), None) => 30,
+            (Ok(price), Some(reference)) => {
+                let divergence_bps = (price.abs_diff(reference) as u128 * 10_000 / reference as u128).min(5_000) as u64;
+                30 - divergence_bps * 30 / 5_000
+            }
+        };
+
+        reserve_points + fee_points + divergence_points
+    }
+
+    // The fee band is only changed through here, so it goes through the same validation as init.
+    pub fn set_fees(&mut self, fee_min: u64, fee_max: u64) -> Result<(), Errors> {
+        let config = PoolConfig { fee_min, fee_max, ..self.config };
+        config.validate()?;
+
+        self.config = config;
+        self.commit("set_fees", &[("fee_min", fee_min), ("fee_max", fee_max)]);
+        Ok(())
+    }
+
+    // Swaps read their price from the oracle instead of config.price while one is set.
+    pub fn set_price_oracle(&mut self, oracle: Box<dyn PriceOracle + Send + Sync>) {
+        self.price_oracle = Some(SharedOracle(Arc::from(oracle)));
+    }
+
+    pub fn clear_price_oracle(&mut self) {
+        self.price_oracle = None;
+    }
+
+    // Every swap variant asks guard about its staked_token_amount and fails with SwapRejectedByPolicy
+    // when it returns false, e.g. while the integrator has trading paused.
+    pub fn set_swap_guard(&mut self, guard: Box<dyn Fn(u64) -> bool + Send + Sync>) {
+        self.swap_guard = Some(SwapGuard(Arc::from(guard)));
+    }
+
+    pub fn clear_swap_guard(&mut self) {
+        self.swap_guard = None;
+    }
+
+    fn swap_price(&self) -> Result<u64, Errors> {
+        let price = match &self.price_oracle {
+            Some(oracle) => oracle.0.price(),
+            None => self.config.price,
+        };
+
+        if price == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+        Ok(price)
+    }
+
+    // Folds other into self. Both pools need the same price and fee curve; the remaining
+    // settings (withdraw fee, rounding, oracle, ...) are kept from self.
+    pub fn merge(&mut self, other: LpPool) -> Result<(), Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.merge(other));
+        }
+
+        let (a, b) = (&self.config, &other.config);
+        if a.price != b.price || a.fee_min != b.fee_min || a.fee_max != b.fee_max || a.liquidity_target != b.liquidity_target {
+            return Err(Errors::IncompatiblePools);
+        }
+
+        let token_reserve = self.token_reserve.checked_add(other.token_reserve).ok_or(Errors::Overflow)?;
+        let staked_token_reserve = self.staked_token_reserve.checked_add(other.staked_token_reserve).ok_or(Errors::Overflow)?;
+        let lp_token_supply = self.lp_token_supply.checked_add(other.lp_token_supply).ok_or(Errors::Overflow)?;
+        let locked_lp_supply = self.locked_lp_supply.checked_add(other.locked_lp_supply).ok_or(Errors::Overflow)?;
+        let collected_fees = self.collected_fees.checked_add(other.collected_fees).ok_or(Errors::Overflow)?;
+        let treasury_balance = self.treasury_balance.checked_add(other.treasury_balance).ok_or(Errors::Overflow)?;
+
+        self.token_reserve = token_reserve;
+        self.staked_token_reserve = staked_token_reserve;
+        self.lp_token_supply = lp_token_supply;
+        self.locked_lp_supply = locked_lp_supply;
+        self.collected_fees = collected_fees;
+        self.treasury_balance = treasury_balance;
+        self.total_fees = self.total_fees.saturating_add(other.total_fees);
+        self.total_gross_swapped = self.total_gross_swapped.saturating_add(other.total_gross_swapped);
+        self.stats.adds = self.stats.adds.saturating_add(other.stats.adds);
+        self.stats.removes = self.stats.removes.saturating_add(other.stats.removes);
+        self.stats.swaps = self.stats.swaps.saturating_add(other.stats.swaps);
+        self.commit("merge", &[
+            ("other_token_reserve", other.token_reserve),
+            ("other_staked_token_reserve", other.staked_token_reserve),
+            ("other_lp_token_supply", other.lp_token_supply),
+        ]);
+        Ok(())
+    }
+
+    // Staking rewards credited to the staked side without minting, so every LP token gains value.
+    pub fn accrue_staked(&mut self, reward: u64) -> Result<(), Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.accrue_staked(reward));
+        }
+
+        if reward == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        if self.lp_token_supply == 0 {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        self.staked_token_reserve = self.staked_token_reserve.checked_add(reward).ok_or(Errors::Overflow)?;
+        self.commit("accrue_staked", &[("reward", reward)]);
+        Ok(())
+    }
+
+    // While set, every deposit, withdrawal, swap, price update, reward accrual, merge and fee
+    // collection returns its usual result but leaves the pool untouched. Setters still apply.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    // While set, every deposit, swap and rebalance fails with PoolPaused; withdrawals keep working so LPs can
+    // always exit.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    fn ensure_not_paused(&self) -> Result<(), Errors> {
+        if self.paused {
+            return Err(Errors::PoolPaused);
+        }
+        Ok(())
+    }
+
+    fn preview<T>(&self, f: impl FnOnce(&mut LpPool) -> Result<T, Errors>) -> Result<T, Errors> {
+        f(&mut self.simulation())
+    }
+
+    // Copy for what-if calculations: operations on it really execute and are not logged.
+    fn simulation(&self) -> LpPool {
+        let mut simulated = self.clone();
+        simulated.dry_run = false;
+        #[cfg(feature = "log")]
+        {
+            simulated.log_writer = None;
+            simulated.pending_log = None;
+        }
+        simulated
+    }
+
+    // Writes one JSON line per successful mutation, with the operation's inputs and outputs
+    // followed by the resulting reserves. Write errors are ignored. Records are written when the
+    // operation commits: nothing is logged for operations a failed transaction rolls back, and a
+    // swap_sliced logs one record for all its slices.
+    #[cfg(feature = "log")]
+    pub fn set_log_writer(&mut self, w: Box<dyn std::io::Write + Send>) {
+        self.log_writer = Some(LogWriter(Arc::new(std::sync::Mutex::new(w))));
+    }
+
+    #[cfg(feature = "log")]
+    fn log_operation(&mut self, op: &str, fields: &[(&str, u64)]) {
+        if self.log_writer.is_none() {
+            return;
+        }
+
+        let mut line = std::format!("{{\"op\":\"{}\"", op);
+        let reserves = [
+            ("token_reserve", self.token_reserve),
+            ("staked_token_reserve", self.staked_token_reserve),
+            ("lp_token_supply", self.lp_token_supply),
+        ];
+        for (name, value) in fields.iter().chain(reserves.iter()) {
+            line.push_str(&std::format!(",\"{}\":{}", name, value));
+        }
+        line.push_str("}\n");
+        self.write_log_line(line);
+    }
+
+    #[cfg(feature = "log")]
+    fn write_log_line(&mut self, line: String) {
+        use std::io::Write;
+
+        if let Some(pending_log) = &mut self.pending_log {
+            pending_log.push(line);
+        } else if let Some(log_writer) = &self.log_writer {
+            if let Ok(mut writer) = log_writer.0.lock() {
+                let _ = writer.write_all(line.as_bytes());
+            }
+        }
+    }
+
+    #[cfg(not(feature = "log"))]
+    fn log_operation(&mut self, _op: &str, _fields: &[(&str, u64)]) {}
+
+    // Every successful mutation ends here, taking the next sequence number.
+    fn commit(&mut self, op: &str, fields: &[(&str, u64)]) {
+        self.sequence = self.sequence.saturating_add(1);
+        self.log_operation(op, fields);
+    }
+
+    // Takes over the state a simulation reached on self's behalf. The simulation's own sequence
+    // numbers are discarded; the caller commits the whole run as one operation.
+    fn adopt(&mut self, simulated: LpPool) {
+        let sequence = self.sequence;
+        #[cfg(feature = "log")]
+        let (log_writer, pending_log) = (self.log_writer.take(), self.pending_log.take());
+
+        *self = simulated;
+        self.sequence = sequence;
+        #[cfg(feature = "log")]
+        {
+            (self.log_writer, self.pending_log) = (log_writer, pending_log);
+        }
+    }
+
+    pub fn apply(&mut self, command: Command) -> Result<CommandResult, Errors> {
+        match command {
+            Command::AddLiquidity(amount) => self.add_liquidity(amount).map(CommandResult::AddLiquidity),
+            Command::RemoveLiquidity(lp_token_amount) => self
+                .remove_liquidity(lp_token_amount)
+                .map(|(token_amount, staked_token_amount)| CommandResult::RemoveLiquidity(token_amount, staked_token_amount)),
+            Command::Swap(staked_token_amount) => self.swap(staked_token_amount).map(CommandResult::Swap),
+        }
+    }
+
+    // Rebuilds a pool by applying log to a fresh one. The first failing command aborts the replay
+    // with its position in log; config errors are returned as they are.
+    pub fn replay(config: PoolConfig, log: &[Command]) -> Result<LpPool, Errors> {
+        let mut lp_pool = LpPool::init(config)?;
+
+        for (index, &command) in log.iter().enumerate() {
+            lp_pool.apply(command).map_err(|error| Errors::ReplayFailed { index, error: Box::new(error) })?;
+        }
+
+        Ok(lp_pool)
+    }
+
+    // Decodes fuzzer input: price, fee_min and fee_max as one byte each and liquidity_target as a
+    // little-endian u16, then 3-byte commands of a tag (taken mod 3: add, remove, swap) and a
+    // little-endian u16 amount. None when the header is short, the config doesn't validate or a
+    // command is cut off.
+    pub fn from_fuzz_bytes(data: &[u8]) -> Option<(LpPool, Vec<Command>)> {
+        let (&[price, fee_min, fee_max, target_low, target_high], commands) = data.split_first_chunk::<5>()?;
+
+        let liquidity_target = u16::from_le_bytes([target_low, target_high]) as u64;
+        let lp_pool = LpPool::init(PoolConfig::new(price as u64, fee_min as u64, fee_max as u64, liquidity_target)).ok()?;
+
+        if commands.len() % 3 != 0 {
+            return None;
+        }
+
+        let commands = commands
+            .chunks_exact(3)
+            .map(|chunk| {
+                let amount = u16::from_le_bytes([chunk[1], chunk[2]]) as u64;
+                match chunk[0] % 3 {
+                    0 => Command::AddLiquidity(amount),
+                    1 => Command::RemoveLiquidity(amount),
+                    _ => Command::Swap(amount),
+                }
+            })
+            .collect();
+
+        Some((lp_pool, commands))
+    }
+
+    // Runs f against the pool and restores the pre-transaction state if it returns an error.
+    pub fn transaction<F>(&mut self, f: F) -> Result<(), Errors>
+    where
+        F: FnOnce(&mut LpPool) -> Result<(), Errors>,
+    {
+        self.staged(f)
+    }
+
+    // transaction for any result type. Log records are held back until f succeeds, so a rolled
+    // back operation leaves no trace in the log either; nested calls hand theirs to the outer one.
+    fn staged<T>(&mut self, f: impl FnOnce(&mut LpPool) -> Result<T, Errors>) -> Result<T, Errors> {
+        let snapshot = self.clone();
+        #[cfg(feature = "log")]
+        let outer_log = self.pending_log.replace(Vec::new());
+
+        let result = f(self);
+        if result.is_err() {
+            *self = snapshot;
+            return result;
+        }
+
+        #[cfg(feature = "log")]
+        {
+            let held = core::mem::replace(&mut self.pending_log, outer_log);
+            for line in held.into_iter().flatten() {
+                self.write_log_line(line);
+            }
+        }
+        result
+    }
+
+    pub fn add_liquidity(&mut self, amount: u64) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.add_liquidity(amount));
+        }
+
+        if amount == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        let token_reserve = match self.config.overflow_policy {
+            OverflowPolicy::Checked => self.token_reserve.checked_add(amount).ok_or(Errors::Overflow)?,
+            OverflowPolicy::Saturating => self.token_reserve.saturating_add(amount),
+        };
+        self.deposit(amount, token_reserve)
+    }
+
+    // For callers holding signed amounts: rejects anything below 1 instead of letting
+    // `as u64` wrap it into a huge deposit.
+    pub fn add_liquidity_signed(&mut self, amount: i64) -> Result<u64, Errors> {
+        if amount <= 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        self.add_liquidity(amount as u64)
+    }
+
+    // Deposits only the part of amount that keeps token_reserve at or below max_reserve and
+    // returns (lp minted, amount left over). A full pool accepts nothing.
+    pub fn add_liquidity_capped(&mut self, amount: u64, max_reserve: u64) -> Result<(u64, u64), Errors> {
+        if amount == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        let accepted = amount.min(max_reserve.saturating_sub(self.token_reserve));
+        if accepted == 0 {
+            return Ok((0, amount));
+        }
+
+        let liquidity_minted = self.add_liquidity(accepted)?;
+        Ok((liquidity_minted, amount - accepted))
+    }
+
+    // Clamps the reserve at u64::MAX instead of returning Overflow; anything deposited above the
+    // clamp is absorbed without backing, so the minted amount understates the deposit.
+    pub fn add_liquidity_saturating(&mut self, amount: u64) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.add_liquidity_saturating(amount));
+        }
+
+        if amount == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        let token_reserve = self.token_reserve.saturating_add(amount);
+        self.deposit(amount, token_reserve)
+    }
+
+    // Deposits both sides at the current reserve ratio; the staked amount may be off by the
+    // rounding of that ratio. Minting is proportional to the pre-deposit reserves, and a first
+    // deposit into an empty pool is valued at the configured price.
+    pub fn add_liquidity_both(&mut self, token_amount: u64, staked_token_amount: u64) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.add_liquidity_both(token_amount, staked_token_amount));
+        }
+
+        if token_amount == 0 && staked_token_amount == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+        self.ensure_not_paused()?;
+
+        let token_reserve = self.token_reserve.checked_add(token_amount).ok_or(Errors::Overflow)?;
+        let staked_token_reserve = self.staked_token_reserve.checked_add(staked_token_amount).ok_or(Errors::Overflow)?;
+
+        let first_deposit = self.lp_token_supply == 0;
+        let liquidity_minted = if first_deposit {
+            let value = token_amount as u128 + staked_token_amount as u128 * self.config.price as u128;
+            self.liquidity_to_mint(u64::try_from(value).map_err(|_| Errors::Overflow)?, 0)?
+        } else if self.token_reserve != 0 {
+            let staked_floor = mul_div(token_amount, self.staked_token_reserve, self.token_reserve, RoundingMode::Floor)?;
+            let staked_ceil = mul_div(token_amount, self.staked_token_reserve, self.token_reserve, RoundingMode::Ceil)?;
+            if staked_token_amount < staked_floor || staked_token_amount > staked_ceil {
+                return Err(Errors::ImbalancedDeposit);
+            }
+            self.liquidity_to_mint(token_amount, self.token_reserve)?
+        } else if self.staked_token_reserve != 0 {
+            if token_amount != 0 {
+                return Err(Errors::ImbalancedDeposit);
+            }
+            self.liquidity_to_mint(staked_token_amount, self.staked_token_reserve)?
+        } else {
+            return Err(Errors::InconsistentState);
+        };
+        let lp_token_supply = self.minted_lp_supply(first_deposit, liquidity_minted)?;
+
+        if first_deposit {
+            self.locked_lp_supply = MINIMUM_LIQUIDITY;
+        }
+
+        self.token_reserve = token_reserve;
+        self.staked_token_reserve = staked_token_reserve;
+        self.lp_token_supply = lp_token_supply;
+        self.stats.adds = self.stats.adds.saturating_add(1);
+        self.commit("add_liquidity_both", &[
+            ("token_amount", token_amount),
+            ("staked_token_amount", staked_token_amount),
+            ("lp_minted", liquidity_minted),
+        ]);
+        Ok(liquidity_minted)
+    }
+
+    // The first deposit permanently locks MINIMUM_LIQUIDITY of the minted LP tokens so the
+    // first depositor can't own the whole supply and skew the share price. Later deposits mint
+    // in proportion to the pre-deposit token reserve, counting only what token_reserve actually
+    // grew by; a pool whose token side is empty can't price a token-only deposit.
+    fn deposit(&mut self, amount: u64, token_reserve: u64) -> Result<u64, Errors> {
+        self.ensure_not_paused()?;
+
+        let first_deposit = self.lp_token_supply == 0;
+        if !first_deposit && self.token_reserve == 0 {
+            return Err(Errors::ImbalancedDeposit);
+        }
+
+        let liquidity_minted = self.liquidity_to_mint(token_reserve - self.token_reserve, self.token_reserve)?;
+        let lp_token_supply = self.minted_lp_supply(first_deposit, liquidity_minted)?;
+
+        self.token_reserve = token_reserve;
+        if first_deposit {
+            self.locked_lp_supply = MINIMUM_LIQUIDITY;
+        }
+        
+        self.lp_token_supply = lp_token_supply;
+        self.stats.adds = self.stats.adds.saturating_add(1);
+        self.commit("add_liquidity", &[("amount", amount), ("lp_minted", liquidity_minted)]);
+        Ok(liquidity_minted)
+    }
+
+    // LP tokens for depositing amount into a side whose pre-deposit reserve is reserve, shared
+    // by every deposit path. The first deposit ignores reserve and mints amount less the locked
+    // MINIMUM_LIQUIDITY; a later one too small to mint a whole LP token is refused rather than
+    // donated to the other LPs, and one worth more LP tokens than a u64 holds fails with Overflow.
+    fn liquidity_to_mint(&self, amount: u64, reserve: u64) -> Result<u64, Errors> {
+        if self.lp_token_supply == 0 {
+            if amount <= MINIMUM_LIQUIDITY {
+                return Err(Errors::InsufficientLiquidity);
+            }
+            return Ok(amount - MINIMUM_LIQUIDITY);
+        }
+
+        match mul_div(amount, self.lp_token_supply, reserve, self.config.rounding)? {
+            0 => Err(Errors::DepositMintsNothing),
+            liquidity_minted => Ok(liquidity_minted),
+        }
+    }
+
+    // lp_token_supply after minting liquidity_minted (plus the locked minimum on a first deposit),
+    // checked against u64::MAX and max_lp_supply.
+    fn minted_lp_supply(&self, first_deposit: bool, liquidity_minted: u64) -> Result<u64, Errors> {
+        let supply = if first_deposit { MINIMUM_LIQUIDITY } else { self.lp_token_supply };
+        let lp_token_supply = supply.checked_add(liquidity_minted).ok_or(Errors::Overflow)?;
+
+        if self.config.max_lp_supply.is_some_and(|max_lp_supply| lp_token_supply > max_lp_supply) {
+            return Err(Errors::SupplyCapExceeded);
+        }
+        Ok(lp_token_supply)
+    }
+
+    pub fn quote_remove_liquidity(&self, lp_token_amount: u64) -> Result<(u64, u64), Errors> {
+        self.quote_remove_liquidity_rounded(lp_token_amount, self.config.rounding)
+    }
+
+    fn quote_remove_liquidity_rounded(&self, lp_token_amount: u64, rounding: RoundingMode) -> Result<(u64, u64), Errors> {
+        if lp_token_amount == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        if lp_token_amount > self.lp_token_supply - self.locked_lp_supply {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        let token_amount = mul_div(lp_token_amount, self.token_reserve, self.lp_token_supply, rounding)?;
+        let staked_token_amount = mul_div(lp_token_amount, self.staked_token_reserve, self.lp_token_supply, rounding)?;
+        
+        if token_amount > self.token_reserve || staked_token_amount > self.staked_token_reserve   {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        let token_amount = token_amount - fee_amount(token_amount, self.config.withdraw_fee);
+        let staked_token_amount = staked_token_amount - fee_amount(staked_token_amount, self.config.withdraw_fee);
+
+        Ok((token_amount, staked_token_amount))
+    }
+
+    // Token side of an LP position, ignoring its staked-token share.
+    pub fn lp_to_token_value(&self, lp_token_amount: u64) -> Result<u64, Errors> {
+        self.quote_remove_liquidity(lp_token_amount).map(|(token_amount, _)| token_amount)
+    }
+
+    // LP tokens to burn so remove_liquidity returns at least target_tokens; the inverse of the
+    // withdrawal math rounded up, grossed up for the withdraw fee.
+    pub fn lp_tokens_for_token_amount(&self, target_tokens: u64) -> Result<u64, Errors> {
+        self.lp_tokens_for_withdrawal(target_tokens, self.token_reserve)
+    }
+
+    // Staked-token counterpart of lp_tokens_for_token_amount.
+    pub fn lp_tokens_for_staked_amount(&self, target_staked: u64) -> Result<u64, Errors> {
+        self.lp_tokens_for_withdrawal(target_staked, self.staked_token_reserve)
+    }
+
+    fn lp_tokens_for_withdrawal(&self, target: u64, reserve: u64) -> Result<u64, Errors> {
+        if target == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        if reserve == 0 || self.config.withdraw_fee >= 100 {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        let gross = mul_div(target, 100, 100 - self.config.withdraw_fee, RoundingMode::Ceil)?;
+        let lp_token_amount = mul_div(gross, self.lp_token_supply, reserve, RoundingMode::Ceil)?;
+
+        if lp_token_amount > self.lp_token_supply - self.locked_lp_supply {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        Ok(lp_token_amount)
+    }
+
+    pub fn remove_liquidity(&mut self, lp_token_amount: u64) ->  Result<(u64, u64), Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.remove_liquidity(lp_token_amount));
+        }
+
+        // Refuse to burn LP tokens for nothing.
+        let (token_amount, staked_token_amount) = self.quote_remove_liquidity(lp_token_amount)?;
+        if token_amount == 0 && staked_token_amount == 0 {
+            return Err(Errors::WithdrawalRoundsToZero);
+        }
+
+        self.withdraw(lp_token_amount, token_amount, staked_token_amount)
+    }
+
+    // Like remove_liquidity, but always rounds the withdrawn amounts down whatever config.rounding
+    // says, and refuses to pay out more per burned LP token than either reserve holds per LP
+    // token. Repeated deposits and withdrawals then can't skim rounding dust from the other LPs.
+    pub fn remove_liquidity_fair(&mut self, lp_token_amount: u64) -> Result<(u64, u64), Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.remove_liquidity_fair(lp_token_amount));
+        }
+
+        let (token_amount, staked_token_amount) = self.quote_remove_liquidity_rounded(lp_token_amount, RoundingMode::Floor)?;
+        if token_amount == 0 && staked_token_amount == 0 {
+            return Err(Errors::WithdrawalRoundsToZero);
+        }
+
+        let exceeds_share = |amount: u64, reserve: u64| amount as u128 * self.lp_token_supply as u128 > lp_token_amount as u128 * reserve as u128;
+        if exceeds_share(token_amount, self.token_reserve) || exceeds_share(staked_token_amount, self.staked_token_reserve) {
+            return Err(Errors::UnfairWithdrawal);
+        }
+
+        self.withdraw(lp_token_amount, token_amount, staked_token_amount)
+    }
+
+    // Burns percent% of lp_token_amount_owned, rounded down.
+    pub fn remove_liquidity_percent(&mut self, lp_token_amount_owned: u64, percent: u8) -> Result<(u64, u64), Errors> {
+        if percent > 100 {
+            return Err(Errors::PercentOutOfRange);
+        }
+
+        self.remove_liquidity(mul_div_floor(lp_token_amount_owned, percent as u64, 100))
+    }
+
+    // Every subtraction is checked and nothing is written until all of them succeed, so a
+    // rounding change upstream surfaces as InsufficientLiquidity instead of an underflow.
+    // Both withdrawn amounts are rounded independently, so the remaining token / staked ratio can
+    // shift by up to a couple of units on each side. In small pools that is a large relative
+    // drift, but never more than rounding explains, which debug builds assert.
+    fn withdraw(&mut self, lp_token_amount: u64, token_amount: u64, staked_token_amount: u64) -> Result<(u64, u64), Errors> {
+        let token_reserve = self.token_reserve.checked_sub(token_amount).ok_or(Errors::InsufficientLiquidity)?;
+        let staked_token_reserve = self.staked_token_reserve.checked_sub(staked_token_amount).ok_or(Errors::InsufficientLiquidity)?;
+        let lp_token_supply = self.lp_token_supply.checked_sub(lp_token_amount).ok_or(Errors::InsufficientLiquidity)?;
+
+        debug_assert!(ratio_drift_within_rounding(self.token_reserve, self.staked_token_reserve, token_reserve, staked_token_reserve));
+
+        self.token_reserve = token_reserve;
+        self.staked_token_reserve = staked_token_reserve;
+        self.lp_token_supply = lp_token_supply;
+        self.stats.removes = self.stats.removes.saturating_add(1);
+        self.commit("remove_liquidity", &[
+            ("lp_token_amount", lp_token_amount),
+            ("token_amount", token_amount),
+            ("staked_token_amount", staked_token_amount),
+        ]);
+
+        Ok((token_amount, staked_token_amount))
+    }
+
+    // Gross token output and fee for a swap, with the same validation as swap.
+    fn quote_swap(&self, staked_token_amount: u64) -> Result<(u64, u64), Errors> {
+        if staked_token_amount ==0  {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        let token_amount = staked_token_amount.checked_mul(self.swap_price()?).ok_or(Errors::Overflow)?;
+        self.quote_swap_output(token_amount)
+    }
+
+    fn quote_swap_output(&self, token_amount: u64) -> Result<(u64, u64), Errors> {
+        let fee_percentage = self.calculate_fee_percentage();
+        let fee = swap_fee_amount(token_amount, fee_percentage);
+
+        if token_amount > self.token_reserve {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        Ok((token_amount, fee))
+    }
+
+    pub fn swap(&mut self, staked_token_amount: u64) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.swap(staked_token_amount));
+        }
+        self.ensure_not_paused()?;
+
+        if self.config.overflow_policy == OverflowPolicy::Saturating {
+            return self.swap_saturating(staked_token_amount);
+        }
+
+        let (token_amount, fee) = self.quote_swap(staked_token_amount)?;
+        self.apply_swap(staked_token_amount, token_amount, fee)
+    }
+
+    // Like swap, but refuses to run while the current fee percentage is above max_fee_percent.
+    pub fn swap_max_fee(&mut self, staked_token_amount: u64, max_fee_percent: u64) -> Result<u64, Errors> {
+        if self.calculate_fee_percentage() > max_fee_percent {
+            return Err(Errors::FeeTooHigh);
+        }
+
+        self.swap(staked_token_amount)
+    }
+
+    // Takes the smallest staked input whose output covers desired_token_out and pays out exactly
+    // that; the rounding surplus of the last staked unit stays in the pool as fee. Returns the
+    // staked input consumed and that surplus (dust) beyond the regular swap fee.
+    // Dust deliberately isn't token_amount % price, the remainder a truncating unswap leaves:
+    // truncating would pay out less than desired_token_out, so the input is rounded up and dust
+    // is what the last staked unit overpays instead. It is 0 when the output plus the fee is a
+    // whole number of staked units.
+    pub fn swap_exact_out(&mut self, desired_token_out: u64) -> Result<(u64, u64), Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.swap_exact_out(desired_token_out));
+        }
+        self.ensure_not_paused()?;
+
+        if desired_token_out == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        if desired_token_out > self.token_reserve {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        let fee_percentage = self.calculate_fee_percentage();
+        if fee_percentage >= 100 {
+            return Err(Errors::FeeTooLarge);
+        }
+
+        let token_amount_needed = (desired_token_out as u128 * 100).div_ceil(100 - fee_percentage as u128);
+        let staked_token_amount = token_amount_needed.div_ceil(self.swap_price()? as u128);
+        let mut staked_token_amount = u64::try_from(staked_token_amount).map_err(|_| Errors::Overflow)?;
+
+        // The estimate ignores the fee rounding up, which can leave the output a unit short.
+        let mut quote = self.quote_swap(staked_token_amount)?;
+        while quote.0 - quote.1 < desired_token_out {
+            staked_token_amount = staked_token_amount.checked_add(1).ok_or(Errors::Overflow)?;
+            quote = self.quote_swap(staked_token_amount)?;
+        }
+
+        let (token_amount, fee) = quote;
+        let dust = token_amount - fee - desired_token_out;
+        self.apply_swap(staked_token_amount, token_amount, token_amount - desired_token_out)?;
+        Ok((staked_token_amount, dust))
+    }
+
+    // Swaps each amount in order against the evolving reserves. Processing halts at the first
+    // failure: its error is the last entry and the remaining orders are not attempted.
+    pub fn swap_batch(&mut self, amounts: &[u64]) -> Vec<Result<u64, Errors>> {
+        if self.dry_run {
+            return self.simulation().swap_batch(amounts);
+        }
+
+        let mut results = Vec::with_capacity(amounts.len());
+
+        for &staked_token_amount in amounts {
+            let result = self.swap(staked_token_amount);
+            let failed = result.is_err();
+            results.push(result);
+
+            if failed {
+                break;
+            }
+        }
+
+        results
+    }
+
+    // Swaps in `slices` equal parts (the remainder goes to the last one) so the fee is re-read
+    // from the depleting reserve between parts. Either every slice executes or none does.
+    pub fn swap_sliced(&mut self, staked_token_amount: u64, slices: u32) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.swap_sliced(staked_token_amount, slices));
+        }
+
+        let slices = slices as u64;
+        if slices == 0 || staked_token_amount / slices == 0 {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        let slice_amount = staked_token_amount / slices;
+        let last_slice_amount = slice_amount + staked_token_amount % slices;
+
+        let mut simulated = self.simulation();
+        let mut total_out = 0;
+        for slice in 0..slices {
+            let amount = if slice + 1 == slices { last_slice_amount } else { slice_amount };
+            total_out += simulated.swap(amount)?;
+        }
+
+        self.adopt(simulated);
+        self.commit("swap_sliced", &[("staked_token_amount", staked_token_amount), ("slices", slices), ("token_out", total_out)]);
+        Ok(total_out)
+    }
+
+    // For protocol-owned rebalancing: same validation and reserve movement as swap, without the fee.
+    pub fn swap_no_fee(&mut self, staked_token_amount: u64) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.swap_no_fee(staked_token_amount));
+        }
+        self.ensure_not_paused()?;
+
+        let (token_amount, _) = self.quote_swap(staked_token_amount)?;
+        self.apply_swap(staked_token_amount, token_amount, 0)
+    }
+
+    // Net output of a swap charged fee_percentage instead of the curve fee, without executing it.
+    pub fn swap_with_fee(&self, staked_token_amount: u64, fee_percentage: u64) -> Result<u64, Errors> {
+        if fee_percentage > 100 {
+            return Err(Errors::FeeOutOfRange);
+        }
+
+        let (token_amount, _) = self.quote_swap(staked_token_amount)?;
+        Ok(token_amount - swap_fee_amount(token_amount, fee_percentage))
+    }
+
+    // For gated callers: charges fee_bps instead of the curve fee, never more than fee_max.
+    pub fn swap_with_fee_override(&mut self, staked_token_amount: u64, fee_bps: u64) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.swap_with_fee_override(staked_token_amount, fee_bps));
+        }
+        self.ensure_not_paused()?;
+
+        if fee_bps > self.config.fee_max.saturating_mul(100) {
+            return Err(Errors::FeeTooLarge);
+        }
+
+        let (token_amount, _) = self.quote_swap(staked_token_amount)?;
+        let fee = swap_fee_amount_bps(token_amount, fee_bps);
+        self.apply_swap(staked_token_amount, token_amount, fee)
+    }
+
+    // Charges the fee_percent of the highest (utilization_threshold, fee_percent) tier that
+    // utilization has reached instead of the curve fee. Below every threshold the curve fee
+    // still applies, so a schedule starting at 0 replaces the curve entirely.
+    pub fn swap_tiered(&mut self, staked_token_amount: u64, schedule: &[(u64, u64)]) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.swap_tiered(staked_token_amount, schedule));
+        }
+        self.ensure_not_paused()?;
+
+        let utilization = self.utilization();
+        let tier = schedule.iter().filter(|&&(threshold, _)| threshold <= utilization).max_by_key(|&&(threshold, _)| threshold);
+
+        let (token_amount, curve_fee) = self.quote_swap(staked_token_amount)?;
+        let fee = match tier {
+            Some(&(_, fee_percentage)) if fee_percentage > 100 => return Err(Errors::FeeOutOfRange),
+            Some(&(_, fee_percentage)) => swap_fee_amount(token_amount, fee_percentage),
+            None => curve_fee,
+        };
+
+        self.apply_swap(staked_token_amount, token_amount, fee)
+    }
+
+    fn apply_swap(&mut self, staked_token_amount: u64, token_amount: u64, fee: u64) -> Result<u64, Errors> {
+        self.ensure_swap_allowed(staked_token_amount)?;
+
+        let staked_token_reserve = self.staked_token_reserve.checked_add(staked_token_amount).ok_or(Errors::Overflow)?;
+        let token_reserve = self.token_reserve_after_swap(token_amount, fee)?;
+
+        self.token_reserve = token_reserve;
+        self.staked_token_reserve = staked_token_reserve;
+        self.record_swap(staked_token_amount, token_amount, fee);
+
+        Ok(token_amount - fee)
+    }
+
+    // Checks every swap variant shares, whatever fee it charges.
+    fn ensure_swap_allowed(&self, staked_token_amount: u64) -> Result<(), Errors> {
+        if self.swap_guard.as_ref().is_some_and(|guard| !(guard.0)(staked_token_amount)) {
+            return Err(Errors::SwapRejectedByPolicy);
+        }
+
+        if self.config.min_swap_amount.is_some_and(|min_swap_amount| staked_token_amount < min_swap_amount) {
+            return Err(Errors::SwapTooSmall);
+        }
+        Ok(())
+    }
+
+    // The LP share of the fee stays in the reserve; the result must respect reserve_floor. A swap
+    // whose fee eats its whole output is refused rather than taking the input for nothing.
+    fn token_reserve_after_swap(&self, token_amount: u64, fee: u64) -> Result<u64, Errors> {
+        if token_amount == fee {
+            return Err(Errors::SwapYieldsNothing);
+        }
+
+        let token_reserve = self.token_reserve - (token_amount - (fee - self.protocol_fee(fee)));
+        if token_reserve < self.config.reserve_floor {
+            return Err(Errors::ReserveFloorBreached);
+        }
+        Ok(token_reserve)
+    }
+
+    fn record_swap(&mut self, staked_token_amount: u64, token_amount: u64, fee: u64) {
+        self.total_fees = self.total_fees.saturating_add(fee);
+        self.total_gross_swapped = self.total_gross_swapped.saturating_add(token_amount);
+        self.collected_fees = self.collected_fees.saturating_add(fee);
+        self.treasury_balance = self.treasury_balance.saturating_add(self.protocol_fee(fee));
+        self.stats.swaps = self.stats.swaps.saturating_add(1);
+        self.commit("swap", &[
+            ("staked_token_amount", staked_token_amount),
+            ("token_amount", token_amount),
+            ("fee", fee),
+            ("token_out", token_amount - fee),
+        ]);
+    }
+
+    // Rounded down, so any remainder stays with the LPs.
+    fn protocol_fee(&self, fee: u64) -> u64 {
+        mul_div_floor(fee, self.config.protocol_fee_share, 100)
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    // Sequence number the next successful mutation will be assigned.
+    pub fn next_sequence(&self) -> u64 {
+        self.sequence + 1
+    }
+
+    // Runs op and tags its result with the sequence number of the last operation it made, so
+    // callers can order and deduplicate results. Failed operations and dry runs don't consume a
+    // number.
+    pub fn with_receipt<T>(&mut self, op: impl FnOnce(&mut LpPool) -> Result<T, Errors>) -> Result<Receipt<T>, Errors> {
+        let sequence = self.sequence;
+        let result = op(self)?;
+        Ok(Receipt { sequence: (self.sequence != sequence).then_some(self.sequence), result })
+    }
+
+    // Whole swap fees charged since the last collect_fees, the LP share left in the reserves
+    // included.
+    pub fn collected_fees(&self) -> u64 {
+        self.collected_fees
+    }
+
+    // Protocol share of swap fees held outside the reserves.
+    pub fn treasury_balance(&self) -> u64 {
+        self.treasury_balance
+    }
+
+    // Pays out treasury_balance and restarts the collected_fees count. The LP share of the fees
+    // stays in the reserves, so only the protocol share is paid; with the default
+    // protocol_fee_share of 100 the two are the same.
+    pub fn collect_fees(&mut self) -> u64 {
+        if self.dry_run {
+            return self.treasury_balance;
+        }
+
+        self.collected_fees = 0;
+        let paid = core::mem::take(&mut self.treasury_balance);
+        self.commit("collect_fees", &[("paid", paid)]);
+        paid
+    }
+
+    // Gross token output of every swap so far.
+    pub fn total_volume(&self) -> u64 {
+        self.total_gross_swapped
+    }
+
+    // Average fee actually paid across all swaps, weighted by gross output.
+    pub fn realized_avg_fee_bps(&self) -> u64 {
+        if self.total_gross_swapped == 0 {
+            return 0;
+        }
+
+        mul_div_floor(self.total_fees, 10_000, self.total_gross_swapped)
+    }
+
+    // Clamps staked_token_amount * price and the staked reserve at u64::MAX instead of returning
+    // Overflow, so the output is priced on the clamped amount rather than the full input.
+    pub fn swap_saturating(&mut self, staked_token_amount: u64) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.swap_saturating(staked_token_amount));
+        }
+        self.ensure_not_paused()?;
+
+        if staked_token_amount ==0  {
+            return Err(Errors::PropertyMustBeGreaterThanZero);
+        }
+
+        self.ensure_swap_allowed(staked_token_amount)?;
+
+        let (token_amount, fee) = self.quote_swap_output(staked_token_amount.saturating_mul(self.swap_price()?))?;
+
+        self.token_reserve = self.token_reserve_after_swap(token_amount, fee)?;
+        self.staked_token_reserve = self.staked_token_reserve.saturating_add(staked_token_amount);
+        self.record_swap(staked_token_amount, token_amount, fee);
+
+        Ok(token_amount - fee)
+    }
+
+    // Like swap, but also reports (net output, token_reserve change, staked_token_reserve change).
+    // Fails with Overflow, leaving the pool untouched, if a change doesn't fit in an i64.
+    pub fn swap_detailed(&mut self, staked_token_amount: u64) -> Result<(u64, i64, i64), Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.swap_detailed(staked_token_amount));
+        }
+
+        let (token_reserve, staked_token_reserve) = (self.token_reserve, self.staked_token_reserve);
+        self.staged(|swapped| {
+            let token_out = swapped.swap(staked_token_amount)?;
+
+            let delta = |before: u64, after: u64| i64::try_from(after as i128 - before as i128).map_err(|_| Errors::Overflow);
+            let token_reserve_delta = delta(token_reserve, swapped.token_reserve)?;
+            let staked_reserve_delta = delta(staked_token_reserve, swapped.staked_token_reserve)?;
+            Ok((token_out, token_reserve_delta, staked_reserve_delta))
+        })
+    }
+
+    // (gross token output, fee, fee percentage) of a swap, without executing it.
+    pub fn swap_breakdown(&self, staked_token_amount: u64) -> Result<(u64, u64, u64), Errors> {
+        let (token_amount, fee) = self.quote_swap(staked_token_amount)?;
+        Ok((token_amount, fee, self.calculate_fee_percentage()))
+    }
+
+    // All-in tokens received per staked token, fee included.
+    pub fn effective_swap_price(&self, staked_token_amount: u64) -> Result<f64, Errors> {
+        let (token_amount, fee) = self.quote_swap(staked_token_amount)?;
+        Ok((token_amount - fee) as f64 / staked_token_amount as f64)
+    }
+
+    // effective_swap_price of a 1-unit swap once staked_token_amount has been swapped.
+    pub fn marginal_price_after(&self, staked_token_amount: u64) -> Result<f64, Errors> {
+        let mut simulated = self.simulation();
+        simulated.swap(staked_token_amount)?;
+        simulated.effective_swap_price(1)
+    }
+
+    // calculate_fee_percentage once staked_token_amount has been swapped. The fee curve rises
+    // with the token reserve, so a draining swap lowers it.
+    pub fn fee_after_swap(&self, staked_token_amount: u64) -> Result<u64, Errors> {
+        let mut simulated = self.simulation();
+        simulated.swap(staked_token_amount)?;
+        Ok(simulated.calculate_fee_percentage())
+    }
+
+    // Percentage by which effective_swap_price falls short of the swap price. Reserves don't move
+    // the price here, so this is the fee after rounding.
+    pub fn price_impact(&self, staked_token_amount: u64) -> Result<f64, Errors> {
+        let price = self.swap_price()? as f64;
+        let effective_price = self.effective_swap_price(staked_token_amount)?;
+        Ok((price - effective_price) / price * 100.0)
+    }
+
+    // Whether swap(staked_token_amount) would currently succeed.
+    pub fn can_swap(&self, staked_token_amount: u64) -> bool {
+        self.simulation().swap(staked_token_amount).is_ok()
+    }
+
+    // True when a 1-unit swap's effective_swap_price is more than threshold_percent away from
+    // external_price. A pool that can't quote that swap offers nothing to arbitrage.
+    pub fn is_arbitrage_open(&self, external_price: u64, threshold_percent: u64) -> bool {
+        let Ok(effective_price) = self.effective_swap_price(1) else {
+            return false;
+        };
+
+        let external_price = external_price as f64;
+        (effective_price - external_price).abs() * 100.0 > threshold_percent as f64 * external_price
+    }
+
+    // Charges the higher of the fees before and after the swap, so it never undercuts swap. The
+    // curve falls as the reserve drains, so only a swap that empties the reserve (charged
+    // fee_max) pays more than swap would.
+    pub fn swap_dynamic_fee(&mut self, staked_token_amount: u64) -> Result<u64, Errors> {
+        if self.dry_run {
+            return self.preview(|lp_pool| lp_pool.swap_dynamic_fee(staked_token_amount));
+        }
+        self.ensure_not_paused()?;
+
+        let (token_amount, fee) = self.quote_swap(staked_token_amount)?;
+        let fee_percentage_after = self.fee_percentage_at(self.token_reserve - token_amount);
+        let fee = fee.max(swap_fee_amount(token_amount, fee_percentage_after));
+
+        self.apply_swap(staked_token_amount, token_amount, fee)
+    }
+
+    pub fn calculate_fee_percentage(&self) -> u64 {
+        self.fee_percentage_at(self.token_reserve)
+    }
+
+    // calculate_fee_percentage in basis points. Swaps charge whole percents, so this is always a
+    // multiple of 100.
+    pub fn current_fee_bps(&self) -> u64 {
+        self.calculate_fee_percentage().saturating_mul(100)
+    }
+
+    // token_reserve as a percentage of liquidity_target, the input to the fee curve. It exceeds
+    // 100 above the target, where the fee curve stops rising, and is only clamped where it would
+    // no longer fit in a u64.
+    pub fn utilization(&self) -> u64 {
+        self.liquidity_ratio_at(self.token_reserve)
+    }
+
+    // token_reserve minus liquidity_target: positive when over-funded, negative when under,
+    // clamped to the i64 range.
+    pub fn distance_to_target(&self) -> i64 {
+        let distance = self.token_reserve as i128 - self.config.liquidity_target as i128;
+        distance.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    // Tokens to deposit to bring token_reserve up to liquidity_target; zero at or above it.
+    pub fn deposit_to_reach_target(&self) -> u64 {
+        self.config.liquidity_target.saturating_sub(self.token_reserve)
+    }
+
+    fn liquidity_ratio_at(&self, token_reserve: u64) -> u64 {
+        let liquidity_ratio = (token_reserve as u128 * 100) / self.config.liquidity_target as u128;
+        liquidity_ratio.min(u64::MAX as u128) as u64
+    }
+
+    // Fee every swap path charges at token_reserve. An empty reserve is charged fee_max rather
+    // than the fee_min the curve starts from, so a quote against a drained pool never advertises
+    // the cheapest fee; the fee therefore steps from fee_max at 0 down to the curve at 1.
+    fn fee_percentage_at(&self, token_reserve: u64) -> u64 {
+        if token_reserve == 0 {
+            return self.config.fee_max;
+        }
+
+        self.fee_percentage_at_ratio(self.liquidity_ratio_at(token_reserve))
+    }
+
+    // Rises linearly from fee_min at a ratio of 0 to fee_max at the target and stays there above it.
+    fn fee_percentage_at_ratio(&self, liquidity_ratio: u64) -> u64 {
+        let liquidity_ratio = liquidity_ratio.min(100);
+        self.config.fee_min + liquidity_ratio * (self.config.fee_max - self.config.fee_min) / 100
+    }
+
+    // Swap fee charged on swap_volume if utilization were at_utilization_percent, read off the
+    // fee curve instead of the live reserve. A utilization of 0 reads fee_min: no reserve is
+    // given, so the empty-reserve fee_max of fee_percentage_at doesn't apply.
+    pub fn projected_fee(&self, swap_volume: u64, at_utilization_percent: u64) -> Result<u64, Errors> {
+        Ok(swap_fee_amount(swap_volume, self.fee_percentage_at_ratio(at_utilization_percent)))
+    }
+
+    // Value lost by depositing amount and withdrawing the minted LP tokens straight away, with
+    // the staked side of the withdrawal valued at the configured price: rounding, the withdraw
+    // fee and, on a first deposit, the locked MINIMUM_LIQUIDITY.
+    pub fn round_trip_cost(&self, amount: u64) -> Result<u64, Errors> {
+        let mut simulated = self.simulation();
+        let minted = simulated.add_liquidity(amount)?;
+        let (token_amount, staked_token_amount) = simulated.remove_liquidity(minted)?;
+
+        let returned = token_amount as u128 + staked_token_amount as u128 * self.config.price as u128;
+        Ok((amount as u128).saturating_sub(returned) as u64)
+    }
+
+    // Gross swap volume (in tokens, as total_volume counts it) whose LP share of the fees, at the
+    // current fee and protocol_fee_share, pays back the withdraw fee on lp_token_amount. Both sides
+    // of the fee are valued at config.price. Fails with NoFeeAccrual if the LPs earn no fees.
+    pub fn volume_to_offset_exit_fee(&self, lp_token_amount: u64) -> Result<u64, Errors> {
+        let mut fee_free = self.simulation();
+        fee_free.config.withdraw_fee = 0;
+        let (gross_token, gross_staked) = fee_free.quote_remove_liquidity(lp_token_amount)?;
+        let (net_token, net_staked) = self.quote_remove_liquidity(lp_token_amount)?;
+
+        let exit_fee = (gross_token - net_token) as u128 + (gross_staked - net_staked) as u128 * self.config.price as u128;
+        if exit_fee == 0 {
+            return Ok(0);
+        }
+
+        // Each unit of volume earns the position fee% * (100 - protocol_fee_share)% of its
+        // lp_token_amount / lp_token_supply share.
+        let lp_fee_rate = self.calculate_fee_percentage() as u128 * (100 - self.config.protocol_fee_share) as u128 * lp_token_amount as u128;
+        if lp_fee_rate == 0 {
+            return Err(Errors::NoFeeAccrual);
+        }
+
+        let volume = exit_fee
+            .checked_mul(10_000 * self.lp_token_supply as u128)
+            .ok_or(Errors::Overflow)?
+            .div_ceil(lp_fee_rate);
+        u64::try_from(volume).map_err(|_| Errors::Overflow)
+    }
+
+    // round_trip_cost without the MINIMUM_LIQUIDITY a first deposit locks, failing with
+    // RoundingLossTooHigh when it is more than the 1 unit rounding alone can explain.
+    pub fn verify_deposit_withdraw_neutral(&self, amount: u64) -> Result<u64, Errors> {
+        let locked = if self.lp_token_supply == 0 { MINIMUM_LIQUIDITY } else { 0 };
+        let loss = self.round_trip_cost(amount)?.saturating_sub(locked);
+        if loss > 1 {
+            return Err(Errors::RoundingLossTooHigh);
+        }
+        Ok(loss)
+    }
+
+    // Fees that swap_volume would earn at the current fee percentage, relative to
+    // total_value_locked. A rough return on liquidity, not annualized.
+    pub fn fee_yield_estimate(&self, swap_volume: u64) -> Result<f64, Errors> {
+        let total_value_locked = self.total_value_locked();
+        if total_value_locked == 0 {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        let fees = swap_volume as u128 * self.calculate_fee_percentage() as u128 / 100;
+        Ok(fees as f64 / total_value_locked as f64)
+    }
+
+    // Total fee charged by running swaps in order, each at the fee of the reserves the previous
+    // ones left behind. The pool itself is untouched; any failing swap fails the whole run.
+    pub fn simulate_fee_revenue(&self, swaps: &[u64]) -> Result<u64, Errors> {
+        let mut simulated = self.simulation();
+        let mut total_fee: u64 = 0;
+
+        for &staked_token_amount in swaps {
+            let (_, fee) = simulated.quote_swap(staked_token_amount)?;
+            simulated.swap(staked_token_amount)?;
+            total_fee = total_fee.checked_add(fee).ok_or(Errors::Overflow)?;
+        }
+
+        Ok(total_fee)
+    }
+
+    // Linear demand model: every percentage point of fee removes demand_elasticity_bps of base_volume,
+    // so revenue f * (10_000 - e * f / 100) peaks at f = 500_000 / e (in bps).
+    pub fn revenue_maximizing_fee_bps(&self, demand_elasticity_bps: u64, base_volume: u64) -> u64 {
+        let fee_min_bps = self.config.fee_min.saturating_mul(100);
+        let fee_max_bps = self.config.fee_max.saturating_mul(100);
+
+        if base_volume == 0 {
+            return fee_min_bps;
+        }
+
+        if demand_elasticity_bps == 0 {
+            return fee_max_bps;
+        }
+
+        (500_000 / demand_elasticity_bps).clamp(fee_min_bps, fee_max_bps)
+    }
+
+    // Fee percentage at which the volume swapped so far (total_gross_swapped) would have earned
+    // impermanent_loss_estimate in fees, rounded up and clamped to [fee_min, fee_max]. Without
+    // any volume no fee can cover a loss, so that gives fee_max.
+    pub fn break_even_fee_percent(&self, impermanent_loss_estimate: u64) -> u64 {
+        if self.total_gross_swapped == 0 {
+            return if impermanent_loss_estimate == 0 { self.config.fee_min } else { self.config.fee_max };
+        }
+
+        let fee_percentage = (impermanent_loss_estimate as u128 * 100).div_ceil(self.total_gross_swapped as u128);
+        fee_percentage.clamp(self.config.fee_min as u128, self.config.fee_max as u128) as u64
+    }
+
+    // Basis-point difference between the current value of lp_owned and simply holding the deposited assets.
+    // Fails with Overflow if either value or the difference doesn't fit.
+    pub fn lp_vs_hold_bps(&self, deposited_token: u64, deposited_staked: u64, lp_owned: u64) -> Result<i64, Errors> {
+        let hold_value = (deposited_staked as i128).checked_mul(self.config.price as i128)
+            .and_then(|staked_value| staked_value.checked_add(deposited_token as i128))
+            .ok_or(Errors::Overflow)?;
+        if hold_value == 0 {
+            return Ok(0);
+        }
+
+        let lp_value = if self.lp_token_supply == 0 {
+            0
+        } else {
+            let lp_value = (lp_owned as u128).checked_mul(self.total_value_locked()).ok_or(Errors::Overflow)? / self.lp_token_supply as u128;
+            i128::try_from(lp_value).map_err(|_| Errors::Overflow)?
+        };
+
+        let difference = (lp_value - hold_value).checked_mul(10_000).ok_or(Errors::Overflow)?;
+        i64::try_from(difference / hold_value).map_err(|_| Errors::Overflow)
+    }
+
+    // Largest staked input whose realized slippage (fee / gross output) stays within slippage_bps.
+    // Fee rounding makes small swaps cheaper than the nominal fee, so below the nominal fee only
+    // gross outputs under 10_000 units can qualify and those are scanned directly.
+    pub fn capacity_at_slippage(&self, slippage_bps: u64) -> Result<u64, Errors> {
+        let max_amount = self.token_reserve / self.swap_price()?;
+        if max_amount == 0 {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        if slippage_bps >= self.calculate_fee_percentage().saturating_mul(100) {
+            return Ok(max_amount);
+        }
+
+        let mut capacity = 0;
+        for amount in 1..=max_amount.min(10_000) {
+            let (token_amount, fee) = self.quote_swap(amount)?;
+            if slippage_bps_of(token_amount, fee) <= slippage_bps {
+                capacity = amount;
+            }
+        }
+
+        Ok(capacity)
+    }
+
+    // Smallest staked_token_amount whose net output beats selling it at external_price. Scans the
+    // same range as capacity_at_slippage.
+    pub fn min_profitable_swap(&self, external_price: u64) -> Result<u64, Errors> {
+        let max_amount = self.token_reserve / self.swap_price()?;
+        if max_amount == 0 {
+            return Err(Errors::InsufficientLiquidity);
+        }
+
+        for amount in 1..=max_amount.min(10_000) {
+            let (token_amount, fee) = self.quote_swap(amount)?;
+            if (token_amount - fee) as u128 > amount as u128 * external_price as u128 {
+                return Ok(amount);
+            }
+        }
+
+        Err(Errors::NotProfitable)
+    }
+
+    // Number of per_swap swaps until calculate_fee_percentage moves off its current value.
+    // Fails with the swap's error if the pool runs dry first. Inside one fee band every swap
+    // drains the reserve by the same amount, so only the first swap and the ones at the band's
+    // edge are simulated; the rest are skipped arithmetically.
+    pub fn swaps_until_fee_band_change(&self, per_swap: u64) -> Result<u64, Errors> {
+        let mut simulated = self.simulation();
+        let fee_percentage = self.calculate_fee_percentage();
+        simulated.swap(per_swap)?;
+        if simulated.calculate_fee_percentage() != fee_percentage {
+            return Ok(1);
+        }
+
+        let drained_per_swap = self.token_reserve - simulated.token_reserve;
+        let skipped = (simulated.token_reserve - self.fee_band_floor(fee_percentage, simulated.token_reserve)) / drained_per_swap;
+        simulated.token_reserve -= skipped * drained_per_swap;
+        if simulated.token_reserve < self.config.reserve_floor {
+            return Err(Errors::ReserveFloorBreached);
+        }
+        let skipped_staked = skipped.checked_mul(per_swap).ok_or(Errors::Overflow)?;
+        simulated.staked_token_reserve = simulated.staked_token_reserve.checked_add(skipped_staked).ok_or(Errors::Overflow)?;
+        let mut swaps = 1 + skipped;
+
+        loop {
+            simulated.swap(per_swap)?;
+            swaps += 1;
+
+            if simulated.calculate_fee_percentage() != fee_percentage {
+                return Ok(swaps);
+            }
+        }
+    }
+
+    // Smallest reserve in [1, token_reserve] charged fee_percentage, given that token_reserve is.
+    // The fee doesn't fall as the reserve grows, so the reserves of one band are contiguous.
+    fn fee_band_floor(&self, fee_percentage: u64, token_reserve: u64) -> u64 {
+        let (mut outside, mut inside) = (0, token_reserve);
+        while inside - outside > 1 {
+            let middle = outside + (inside - outside) / 2;
+            if self.fee_percentage_at(middle) == fee_percentage {
+                inside = middle;
+            } else {
+                outside = middle;
+            }
+        }
+        inside
+    }
+
+    // Compares the share of the pool (minted / lp_token_supply after the deposit) that amount
+    // buys now against the share it buys after pending_swap executes. Ties favour depositing now.
+    pub fn deposit_timing_advice(&self, amount: u64, pending_swap: u64) -> Result<DepositTiming, Errors> {
+        let mut now = self.simulation();
+        let minted_now = now.add_liquidity(amount)?;
+
+        let mut after_swap = self.simulation();
+        after_swap.swap(pending_swap)?;
+        let minted_after = after_swap.add_liquidity(amount)?;
+
+        let share_now = minted_now as u128 * after_swap.lp_token_supply as u128;
+        let share_after = minted_after as u128 * now.lp_token_supply as u128;
+        if share_after > share_now {
+            Ok(DepositTiming::AfterSwap)
+        } else {
+            Ok(DepositTiming::Now)
+        }
+    }
+
+    // Change of token_reserve caused by a swap of the given size, in basis points of the reserve
+    // per staked token swapped (negative as the reserve drains), rounded towards zero.
+    pub fn reserve_elasticity(&self, staked_token_amount: u64) -> Result<i64, Errors> {
+        let mut simulated = self.simulation();
+        simulated.swap(staked_token_amount)?;
+
+        let before = self.token_reserve as i128;
+        let after = simulated.token_reserve as i128;
+
+        let change = (after - before).checked_mul(10_000).ok_or(Errors::Overflow)?;
+        let per_staked = before.checked_mul(staked_token_amount as i128).ok_or(Errors::Overflow)?;
+        i64::try_from(change / per_staked).map_err(|_| Errors::Overflow)
+    }
+}
+
+// LpPool shared between threads: queries take the read lock, mutations the write lock. A panic
+// while holding the lock may leave the pool half-updated (f can be any closure), so every later
+// access fails with PoolPoisoned instead of exposing it.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct SharedLpPool(Arc<std::sync::RwLock<LpPool>>);
+
+#[cfg(feature = "std")]
+impl SharedLpPool {
+
+    pub fn new(lp_pool: LpPool) -> Self {
+        SharedLpPool(Arc::new(std::sync::RwLock::new(lp_pool)))
+    }
+
+    pub fn read<R>(&self, f: impl FnOnce(&LpPool) -> R) -> Result<R, Errors> {
+        let lp_pool = self.0.read().map_err(|_| Errors::PoolPoisoned)?;
+        Ok(f(&lp_pool))
+    }
+
+    pub fn write<R>(&self, f: impl FnOnce(&mut LpPool) -> R) -> Result<R, Errors> {
+        let mut lp_pool = self.0.write().map_err(|_| Errors::PoolPoisoned)?;
+        Ok(f(&mut lp_pool))
+    }
+
+    pub fn add_liquidity(&self, amount: u64) -> Result<u64, Errors> {
+        self.write(|lp_pool| lp_pool.add_liquidity(amount))?
+    }
+
+    pub fn remove_liquidity(&self, lp_token_amount: u64) -> Result<(u64, u64), Errors> {
+        self.write(|lp_pool| lp_pool.remove_liquidity(lp_token_amount))?
+    }
+
+    pub fn swap(&self, staked_token_amount: u64) -> Result<u64, Errors> {
+        self.write(|lp_pool| lp_pool.swap(staked_token_amount))?
+    }
+
+    pub fn calculate_fee_percentage(&self) -> Result<u64, Errors> {
+        self.read(|lp_pool| lp_pool.calculate_fee_percentage())
+    }
+
+    pub fn total_value_locked(&self) -> Result<u128, Errors> {
+        self.read(|lp_pool| lp_pool.total_value_locked())
+    }
+
+    pub fn lp_token_price(&self) -> Result<Option<f64>, Errors> {
+        self.read(|lp_pool| lp_pool.lp_token_price())
+    }
+
+    pub fn snapshot(&self) -> Result<LpPool, Errors> {
+        self.read(|lp_pool| lp_pool.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;    
+    
+    // errors
+
+    #[test]
+    fn test_errors_box_into_dyn_error() {
+        fn fails() -> Result<LpPool, Box<dyn std::error::Error>> {
+            Ok(LpPool::init(PoolConfig::new(0, 1, 2, 1000))?)
+        }
+
+        let error = fails().unwrap_err();
+        assert_eq!(error.to_string(), Errors::InvalidParameter { field: "price", reason: "must be greater than zero" }.to_string());
+        assert_eq!(error.to_string(), "Invalid parameter: price must be greater than zero");
+        assert!(error.source().is_none());
+
+        let error = Errors::InvalidParameter { field: "fee_max", reason: "must be at most 100" };
+        assert_eq!(error.to_string(), "Invalid parameter: fee_max must be at most 100");
+    }
+
+    #[test]
+    fn test_errors_display_through_core_fmt() {
+        use core::fmt::Write;
+
+        let mut message = alloc::string::String::new();
+        write!(message, "{}", Errors::InsufficientLiquidity).unwrap();
+        assert_eq!(message, "Insufficient liquidity");
+    }
+
+    // parse_pool
+
+    #[test]
+    fn test_parse_pool_valid() {
+        let lp_pool = parse_pool(&["100", "1", "2", "1000"]);
+        assert_eq!(lp_pool, LpPool::init(PoolConfig::new(100, 1, 2, 1000)));
+    }
+
+    #[test]
+    fn test_parse_pool_non_numeric() {
+        assert_eq!(parse_pool(&["100", "one", "2", "1000"]), Err(Errors::ParseError));
+        assert_eq!(parse_pool(&["100", "-1", "2", "1000"]), Err(Errors::ParseError));
+    }
+
+    #[test]
+    fn test_parse_pool_wrong_argument_count() {
+        assert_eq!(parse_pool(&["100", "1", "2"]), Err(Errors::ParseError));
+        assert_eq!(parse_pool(&["100", "1", "2", "1000", "5"]), Err(Errors::ParseError));
+    }
+
+    #[test]
+    fn test_parse_pool_still_validates() {
+        assert_eq!(parse_pool(&["0", "1", "2", "1000"]), Err(Errors::InvalidParameter { field: "price", reason: "must be greater than zero" }));
+    }
+
+    // init
+
+    #[test]
+    fn test_init_success() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 5, 6, 1000));
+        assert!(lp_pool.is_ok());
+    }
+
+    #[test]
+    fn test_init_fee_max_at_100_percent() {
+        assert!(LpPool::init(PoolConfig::new(100, 99, 100, 1000)).is_ok());
+    }
+
+    #[test]
+    fn test_init_fee_max_above_100_percent() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 5, 101, 1000));
+        assert_eq!(lp_pool, Err(Errors::FeeOutOfRange));
+    }
+
+    #[test]
+    fn test_init_zero_price() {
+        let lp_pool = LpPool::init(PoolConfig::new(0, 5, 1, 1000));
+        assert_eq!(lp_pool, Err(Errors::InvalidParameter { field: "price", reason: "must be greater than zero" }));
+    }
+
+    #[test]
+    fn test_init_zero_fee_min() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 0, 1, 1000));
+        assert_eq!(lp_pool, Err(Errors::InvalidParameter { field: "fee_min", reason: "must be greater than zero" }));
+    }
+
+    #[test]
+    fn test_init_zero_fee_max() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 5, 0, 1000));
+        assert_eq!(lp_pool, Err(Errors::InvalidParameter { field: "fee_max", reason: "must be greater than zero" }));
+    }
+
+    #[test]
+    fn test_init_zero_liquidity_target() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 5, 1, 0));
+        assert_eq!(lp_pool, Err(Errors::InvalidParameter { field: "liquidity_target", reason: "must be greater than zero" }));
+    }
+
+    #[test]
+    fn test_init_fee_min_greater_than_fee_max() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 5, 4, 1000));
+        assert_eq!(lp_pool, Err(Errors::FeeMaxMustBeGreaterThanFeeMin));
+    }
+
+    #[test]
+    fn test_init_fee_min_equal_to_fee_max() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 5, 5, 1000));
+        assert_eq!(lp_pool, Err(Errors::FeeMaxMustBeGreaterThanFeeMin));
+    }
+
+    #[test]
+    fn test_init_all_properties_zero() {
+        let lp_pool = LpPool::init(PoolConfig::new(0, 0, 0, 0));
+        assert_eq!(lp_pool, Err(Errors::InvalidParameter { field: "price", reason: "must be greater than zero" }));
+    }
+
+    #[test]
+    fn test_init_keeps_config() {
+        let config = PoolConfig::new(100, 5, 6, 1000);
+        let lp_pool = LpPool::init(config).unwrap();
+        assert_eq!(lp_pool.config, config);
+    }
+
+    #[test]
+    fn test_config_unchanged_by_operations() {
+        let config = PoolConfig::new(100, 1, 2, 1000);
+        let mut lp_pool = LpPool::init(config).unwrap();
+        let _ = lp_pool.add_liquidity(2000);
+        let _ = lp_pool.swap(5);
+        let _ = lp_pool.remove_liquidity(100);
+        assert_eq!(lp_pool.config, config);
+    }
+
+    #[test]
+    fn test_config_validate() {
+        assert_eq!(PoolConfig::new(100, 5, 6, 1000).validate(), Ok(()));
+        assert_eq!(PoolConfig::new(100, 0, 6, 1000).validate(), Err(Errors::InvalidParameter { field: "fee_min", reason: "must be greater than zero" }));
+        assert_eq!(PoolConfig::new(100, 6, 5, 1000).validate(), Err(Errors::FeeMaxMustBeGreaterThanFeeMin));
+    }
+
+    // init_with_reserves
+
+    #[test]
+    fn test_init_with_reserves_valid_seed() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 200, 300, 500).unwrap();
+        assert_eq!(lp_pool.token_reserve, 200);
+        assert_eq!(lp_pool.staked_token_reserve, 300);
+        assert_eq!(lp_pool.lp_token_supply, 500);
+    }
+
+    #[test]
+    fn test_init_with_reserves_inconsistent_state() {
+        let config = PoolConfig::new(100, 1, 2, 1000);
+        assert_eq!(LpPool::init_with_reserves(config, 200, 0, 0), Err(Errors::InconsistentState));
+        assert_eq!(LpPool::init_with_reserves(config, 0, 300, 0), Err(Errors::InconsistentState));
+    }
+
+    #[test]
+    fn test_init_with_reserves_validates_config() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(0, 1, 2, 1000), 200, 300, 500);
+        assert_eq!(lp_pool, Err(Errors::InvalidParameter { field: "price", reason: "must be greater than zero" }));
+    }
+
+    #[test]
+    fn test_init_with_reserves_empty() {
+        let config = PoolConfig::new(100, 1, 2, 1000);
+        assert_eq!(LpPool::init_with_reserves(config, 0, 0, 0), LpPool::init(config));
+    }
+
+    // check_invariants
+
+    #[test]
+    fn test_check_invariants_fresh_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_check_invariants_after_operations() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.add_liquidity(2000).unwrap();
+        lp_pool.swap(5).unwrap();
+        assert_eq!(lp_pool.check_invariants(), Ok(()));
+
+        lp_pool.remove_liquidity(1000).unwrap();
+        assert_eq!(lp_pool.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_check_invariants_corrupted_config() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.config.fee_min = 3;
+        assert_eq!(lp_pool.check_invariants(), Err(Errors::FeeMaxMustBeGreaterThanFeeMin));
+
+        lp_pool.config.price = 0;
+        assert_eq!(lp_pool.check_invariants(), Err(Errors::InvalidParameter { field: "price", reason: "must be greater than zero" }));
+    }
+
+    #[test]
+    fn test_check_invariants_corrupted_reserves() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 100;
+        assert_eq!(lp_pool.check_invariants(), Err(Errors::InconsistentState));
+
+        lp_pool.token_reserve = 0;
+        lp_pool.lp_token_supply = 100;
+        assert_eq!(lp_pool.check_invariants(), Err(Errors::InconsistentState));
+    }
+
+    // update_price_and_rebalance
+
+    #[test]
+    fn test_update_price_and_rebalance_safe_change() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 500, 40, 860).unwrap();
+        assert_eq!(lp_pool.update_price_and_rebalance(9), Ok(()));
+        assert_eq!(lp_pool.config.price, 9);
+        assert!(lp_pool.is_solvent());
+        assert_eq!(lp_pool.update_price_and_rebalance(0), Err(Errors::PropertyMustBeGreaterThanZero));
+    }
+
+    #[test]
+    fn test_update_price_and_rebalance_rejects_insolvency() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 500, 40, 860).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.update_price_and_rebalance(8), Err(Errors::WouldBecomeInsolvent));
+        assert_eq!(lp_pool, before);
+        assert!(lp_pool.clone().update_price(8).is_ok());
+    }
+
+    // update_price
+
+    #[test]
+    fn test_update_price_zero() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        let result = lp_pool.update_price(0);
+        assert_eq!(result, Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.config.price, 100);
+    }
+
+    #[test]
+    fn test_update_price_valid_bump() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.update_price(150), Ok(()));
+        assert_eq!(lp_pool.config.price, 150);
+        assert_eq!(lp_pool.update_price(300), Ok(()));
+        assert_eq!(lp_pool.config.price, 300);
+        assert_eq!(lp_pool.update_price(150), Ok(()));
+        assert_eq!(lp_pool.config.price, 150);
+    }
+
+    #[test]
+    fn test_update_price_excessive_jump() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.update_price(201), Err(Errors::PriceChangeTooLarge));
+        assert_eq!(lp_pool.update_price(49), Err(Errors::PriceChangeTooLarge));
+        assert_eq!(lp_pool.config.price, 100);
+    }
+
+    #[test]
+    fn test_swap_uses_updated_price() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        lp_pool.update_price(50).unwrap();
+
+        let result = lp_pool.swap(10).unwrap();
+        assert_eq!(result, 490);
+    }
+
+    // price_checked
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_price_checked_fresh_and_stale() {
+        use std::time::Duration;
+
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.price_checked(Duration::from_secs(60)), Err(Errors::StalePrice));
+
+        lp_pool.update_price(150).unwrap();
+        let updated_at = lp_pool.last_price_update.unwrap();
+        assert_eq!(lp_pool.price_checked(Duration::from_secs(60)), Ok(150));
+        assert_eq!(lp_pool.price_checked_at(updated_at + Duration::from_secs(60), Duration::from_secs(60)), Ok(150));
+        assert_eq!(lp_pool.price_checked_at(updated_at + Duration::from_secs(61), Duration::from_secs(60)), Err(Errors::StalePrice));
+    }
+
+    // price_history
+
+    #[test]
+    fn test_price_history_keeps_last_updates_in_order() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert!(lp_pool.price_history().is_empty());
+
+        let updates: Vec<u64> = (101..101 + PRICE_HISTORY_CAPACITY as u64 + 3).collect();
+        for &price in &updates {
+            lp_pool.update_price(price).unwrap();
+        }
+        assert_eq!(lp_pool.update_price(1), Err(Errors::PriceChangeTooLarge));
+
+        assert_eq!(lp_pool.price_history().len(), PRICE_HISTORY_CAPACITY);
+        assert!(lp_pool.price_history().iter().eq(updates[3..].iter()));
+    }
+
+    // twap
+
+    #[test]
+    fn test_twap_over_window() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        for price in [120, 90, 100, 110, 130] {
+            lp_pool.update_price(price).unwrap();
+        }
+
+        assert_eq!(lp_pool.twap(1), Some(130));
+        assert_eq!(lp_pool.twap(3), Some((100 + 110 + 130) / 3));
+        assert_eq!(lp_pool.twap(5), Some(110));
+    }
+
+    #[test]
+    fn test_twap_not_enough_points() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.twap(1), None);
+
+        lp_pool.update_price(120).unwrap();
+        assert_eq!(lp_pool.twap(2), None);
+        assert_eq!(lp_pool.twap(0), None);
+    }
+
+    // resilience_score
+
+    #[test]
+    fn test_resilience_score_healthy_pool() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 5, 1000), 1000, 10, 2000).unwrap();
+        assert_eq!(lp_pool.resilience_score(), 100);
+
+        for price in [98, 101, 100, 99] {
+            lp_pool.update_price(price).unwrap();
+        }
+        lp_pool.swap(1).unwrap();
+        let score = lp_pool.resilience_score();
+        assert!((90..=100).contains(&score), "{}", score);
+    }
+
+    #[test]
+    fn test_resilience_score_drained_drifted_pool() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 5, 1000), 1000, 10, 2000).unwrap();
+        for price in [100, 100, 100] {
+            lp_pool.update_price(price).unwrap();
+        }
+        lp_pool.swap(9).unwrap();
+        lp_pool.set_price_oracle(Box::new(FixedPrice(190)));
+
+        let score = lp_pool.resilience_score();
+        assert!(score <= 20, "{}", score);
+
+        lp_pool.set_price_oracle(Box::new(FixedPrice(0)));
+        assert!(lp_pool.resilience_score() <= score);
+    }
+
+    // set_fees
+
+    #[test]
+    fn test_set_fees_valid() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.set_fees(2, 8), Ok(()));
+        assert_eq!(lp_pool.config().fee_min, 2);
+        assert_eq!(lp_pool.config().fee_max, 8);
+        assert_eq!(lp_pool.calculate_fee_percentage(), 8);
+    }
+
+    #[test]
+    fn test_set_fees_zero() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.set_fees(0, 5), Err(Errors::InvalidParameter { field: "fee_min", reason: "must be greater than zero" }));
+        assert_eq!(lp_pool.set_fees(1, 0), Err(Errors::InvalidParameter { field: "fee_max", reason: "must be greater than zero" }));
+        assert_eq!(lp_pool.config(), &PoolConfig::new(10, 1, 5, 1000));
+    }
+
+    #[test]
+    fn test_set_fees_above_100_percent() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.set_fees(1, 100), Ok(()));
+        assert_eq!(lp_pool.set_fees(1, 150), Err(Errors::FeeOutOfRange));
+        assert_eq!(lp_pool.config().fee_max, 100);
+    }
+
+    #[test]
+    fn test_set_fees_min_not_below_max() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.set_fees(5, 5), Err(Errors::FeeMaxMustBeGreaterThanFeeMin));
+        assert_eq!(lp_pool.set_fees(6, 5), Err(Errors::FeeMaxMustBeGreaterThanFeeMin));
+        assert_eq!(lp_pool.config(), &PoolConfig::new(10, 1, 5, 1000));
+    }
+
+    // merge
+
+    #[test]
+    fn test_merge_compatible_pools() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.add_liquidity(2000).unwrap();
+        let mut other = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        other.add_liquidity(3000).unwrap();
+        other.swap(10).unwrap();
+
+        assert_eq!(lp_pool.merge(other), Ok(()));
+        assert_eq!(lp_pool.token_reserve, 2000 + 2900);
+        assert_eq!(lp_pool.staked_token_reserve, 10);
+        assert_eq!(lp_pool.lp_token_supply, 2000 + 3000);
+        assert_eq!(lp_pool.locked_lp_supply, 2 * MINIMUM_LIQUIDITY);
+        assert_eq!(lp_pool.collected_fees(), 5);
+        assert_eq!(lp_pool.stats(), Stats { adds: 2, removes: 0, swaps: 1 });
+        assert_eq!(lp_pool.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_merge_rejects_different_price() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let before = lp_pool.clone();
+        let other = LpPool::init_with_reserves(PoolConfig::new(11, 1, 5, 1000), 1000, 0, 1000).unwrap();
+
+        assert_eq!(lp_pool.merge(other), Err(Errors::IncompatiblePools));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_merge_overflow() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), u64::MAX, 0, 1000).unwrap();
+        let before = lp_pool.clone();
+        let other = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1, 0, 1).unwrap();
+
+        assert_eq!(lp_pool.merge(other), Err(Errors::Overflow));
+        assert_eq!(lp_pool, before);
+    }
+
+    // accrue_staked
+
+    #[test]
+    fn test_accrue_staked_raises_lp_value() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 100, 1000).unwrap();
+        let mut unaccrued = lp_pool.clone();
+        let price_before = lp_pool.lp_token_price().unwrap();
+
+        assert_eq!(lp_pool.accrue_staked(50), Ok(()));
+        assert!(lp_pool.lp_token_price().unwrap() > price_before);
+        assert_eq!(lp_pool.lp_token_supply, 1000);
+        assert_eq!(lp_pool.remove_liquidity(100), Ok((100, 15)));
+        assert_eq!(unaccrued.remove_liquidity(100), Ok((100, 10)));
+    }
+
+    #[test]
+    fn test_accrue_staked_rejections() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.accrue_staked(50), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.accrue_staked(0), Err(Errors::PropertyMustBeGreaterThanZero));
+
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, u64::MAX, 1000).unwrap();
+        assert_eq!(lp_pool.accrue_staked(1), Err(Errors::Overflow));
+        assert_eq!(lp_pool.staked_token_reserve, u64::MAX);
+    }
+
+    // apply
+
+    #[test]
+    fn test_apply_matches_direct_calls() {
+        let config = PoolConfig::new(10, 1, 5, 1000);
+        let mut applied = LpPool::init(config).unwrap();
+        let mut direct = LpPool::init(config).unwrap();
+
+        let commands = [Command::AddLiquidity(2000), Command::Swap(10), Command::RemoveLiquidity(500), Command::AddLiquidity(300)];
+        let results: Vec<_> = commands.iter().map(|&command| applied.apply(command).unwrap()).collect();
+
+        assert_eq!(results, [
+            CommandResult::AddLiquidity(direct.add_liquidity(2000).unwrap()),
+            CommandResult::Swap(direct.swap(10).unwrap()),
+            {
+                let (token_amount, staked_token_amount) = direct.remove_liquidity(500).unwrap();
+                CommandResult::RemoveLiquidity(token_amount, staked_token_amount)
+            },
+            CommandResult::AddLiquidity(direct.add_liquidity(300).unwrap()),
+        ]);
+        assert_eq!(applied, direct);
+    }
+
+    #[test]
+    fn test_apply_passes_errors_through() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.apply(Command::Swap(0)), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.apply(Command::RemoveLiquidity(1)), Err(Errors::InsufficientLiquidity));
+    }
+
+    // operation_weight
+
+    #[test]
+    fn test_operation_weight_ordering() {
+        let add = operation_weight(&Command::AddLiquidity(1));
+        let remove = operation_weight(&Command::RemoveLiquidity(1));
+        let swap = operation_weight(&Command::Swap(1));
+
+        assert!(add < remove);
+        assert!(remove < swap);
+        assert_eq!((add, remove, swap), (1, 4, 5));
+        assert_eq!(operation_weight(&Command::Swap(u64::MAX)), swap);
+    }
+
+    // weighted_entry_value
+
+    #[test]
+    fn test_weighted_entry_value_two_deposits() {
+        assert_eq!(weighted_entry_value(&[(100, 2), (300, 4)]), 3.5);
+        assert_eq!(weighted_entry_value(&[(100, 2)]), 2.0);
+    }
+
+    #[test]
+    fn test_weighted_entry_value_without_positions() {
+        assert_eq!(weighted_entry_value(&[]), 0.0);
+        assert_eq!(weighted_entry_value(&[(0, 5)]), 0.0);
+    }
+
+    // replay
+
+    #[test]
+    fn test_replay_matches_manual_application() {
+        let config = PoolConfig::new(10, 1, 5, 1000);
+        let log = [Command::AddLiquidity(2000), Command::Swap(10), Command::RemoveLiquidity(500)];
+
+        let mut manual = LpPool::init(config).unwrap();
+        manual.add_liquidity(2000).unwrap();
+        manual.swap(10).unwrap();
+        manual.remove_liquidity(500).unwrap();
+
+        assert_eq!(LpPool::replay(config, &log), Ok(manual));
+        assert_eq!(LpPool::replay(config, &[]), LpPool::init(config));
+    }
+
+    #[test]
+    fn test_replay_reports_failing_index() {
+        let config = PoolConfig::new(10, 1, 5, 1000);
+        let log = [Command::AddLiquidity(2000), Command::Swap(10), Command::Swap(1000), Command::Swap(1)];
+
+        let error = LpPool::replay(config, &log).unwrap_err();
+        assert_eq!(error, Errors::ReplayFailed { index: 2, error: Box::new(Errors::InsufficientLiquidity) });
+        assert_eq!(error.to_string(), "Replay failed at command 2: Insufficient liquidity");
+        assert_eq!(core::error::Error::source(&error).map(|source| source.to_string()), Some("Insufficient liquidity".to_string()));
+
+        assert_eq!(LpPool::replay(PoolConfig::new(0, 1, 5, 1000), &log), Err(Errors::InvalidParameter { field: "price", reason: "must be greater than zero" }));
+    }
+
+    // from_fuzz_bytes
+
+    #[test]
+    fn test_from_fuzz_bytes_decodes_fixed_input() {
+        let data = [10, 1, 5, 0xe8, 0x03, 0, 0xd0, 0x07, 2, 10, 0, 4, 0xf4, 0x01];
+        let (lp_pool, commands) = LpPool::from_fuzz_bytes(&data).unwrap();
+
+        assert_eq!(lp_pool, LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap());
+        assert_eq!(commands, vec![Command::AddLiquidity(2000), Command::Swap(10), Command::RemoveLiquidity(500)]);
+        assert_eq!(LpPool::from_fuzz_bytes(&data), Some((lp_pool, commands)));
+    }
+
+    #[test]
+    fn test_from_fuzz_bytes_invalid_input() {
+        assert_eq!(LpPool::from_fuzz_bytes(&[]), None);
+        assert_eq!(LpPool::from_fuzz_bytes(&[10, 1, 5, 0xe8]), None);
+        assert_eq!(LpPool::from_fuzz_bytes(&[10, 5, 1, 0xe8, 0x03]), None);
+        assert_eq!(LpPool::from_fuzz_bytes(&[10, 1, 5, 0xe8, 0x03, 0, 0xd0]), None);
+        assert_eq!(LpPool::from_fuzz_bytes(&[10, 1, 5, 0xe8, 0x03]).map(|(_, commands)| commands), Some(vec![]));
+    }
+
+     // transaction
+
+    #[test]
+    fn test_transaction_rolls_back_on_failure() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.add_liquidity(1500).unwrap();
+        let before = lp_pool.clone();
+
+        let result = lp_pool.transaction(|pool| {
+            pool.add_liquidity(500)?;
+            pool.swap(21)?;
+            Ok(())
+        });
+
+        assert_eq!(result, Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_transaction_commits_on_success() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+
+        let result = lp_pool.transaction(|pool| {
+            pool.add_liquidity(2000)?;
+            pool.swap(5)?;
+            Ok(())
+        });
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(lp_pool.token_reserve, 1500);
+        assert_eq!(lp_pool.staked_token_reserve, 5);
+        assert_eq!(lp_pool.lp_token_supply, 2000);
+    }
+
+    // add_liquidity
+
+    #[test]
+    fn test_add_liquidity_first_time() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        let liquidity_added: Result<u64, Errors> = lp_pool.add_liquidity(2000);
+        assert_eq!(liquidity_added, Ok(2000 - MINIMUM_LIQUIDITY));
+        assert_eq!(lp_pool.lp_token_supply, 2000);
+        assert_eq!(lp_pool.locked_lp_supply, MINIMUM_LIQUIDITY);
+        assert_eq!(lp_pool.token_reserve, 2000);
+    }
+
+    #[test]
+    fn test_add_liquidity_token_reserve() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        let _ =  lp_pool.add_liquidity(2000);        
+        let _ =  lp_pool.add_liquidity(3000);
+        assert_eq!(lp_pool.token_reserve, 5000);
+        
+    }
+    #[test]
+    fn test_add_liquidity_minted_tokens_twice() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        let minted_tokens1 =  lp_pool.add_liquidity(2000);        
+        assert_eq!(minted_tokens1, Ok(1000));
+        let minted_tokens2 =  lp_pool.add_liquidity(3000);
+        assert_eq!(minted_tokens2, Ok(3000));
+        
+    }
+
+    #[test]
+    fn test_add_liquidity_zero_amount() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        let result = lp_pool.add_liquidity(0);
+        assert_eq!(result, Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.lp_token_supply, 0);
+        assert_eq!(lp_pool.token_reserve, 0);
+    }
+
+    #[test]
+    fn test_add_liquidity_under_supply_cap() {
+        let mut config = PoolConfig::new(100, 5, 10, 1000);
+        config.max_lp_supply = Some(5000);
+        let mut lp_pool = LpPool::init(config).unwrap();
+
+        assert_eq!(lp_pool.add_liquidity(2000), Ok(1000));
+        assert_eq!(lp_pool.add_liquidity(3000), Ok(3000));
+        assert_eq!(lp_pool.lp_token_supply, 5000);
+        assert_eq!(lp_pool.add_liquidity(1), Err(Errors::SupplyCapExceeded));
+    }
+
+    #[test]
+    fn test_add_liquidity_exceeding_supply_cap() {
+        let mut config = PoolConfig::new(100, 5, 10, 1000);
+        config.max_lp_supply = Some(3000);
+        let mut lp_pool = LpPool::init(config).unwrap();
+        lp_pool.add_liquidity(2000).unwrap();
+
+        assert_eq!(lp_pool.add_liquidity(3000), Err(Errors::SupplyCapExceeded));
+        assert_eq!(lp_pool.token_reserve, 2000);
+        assert_eq!(lp_pool.lp_token_supply, 2000);
+
+        let mut lp_pool = LpPool::init(config).unwrap();
+        assert_eq!(lp_pool.add_liquidity(3001), Err(Errors::SupplyCapExceeded));
+        assert_eq!(lp_pool.token_reserve, 0);
+        assert_eq!(lp_pool.locked_lp_supply, 0);
+    }
+
+    #[test]
+    fn test_add_liquidity_supply_overflow() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 5, 10, 1000), 2, 0, u64::MAX - 1).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.add_liquidity(2), Err(Errors::Overflow));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_add_liquidity_first_deposit_below_minimum() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        assert_eq!(lp_pool.add_liquidity(MINIMUM_LIQUIDITY), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.lp_token_supply, 0);
+        assert_eq!(lp_pool.token_reserve, 0);
+    }
+
+    #[test]
+    fn test_locked_liquidity_cannot_be_withdrawn() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        let minted = lp_pool.add_liquidity(5000).unwrap();
+        assert_eq!(minted, 4000);
+
+        assert_eq!(lp_pool.remove_liquidity(minted + 1), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.remove_liquidity(minted), Ok((4000, 0)));
+        assert_eq!(lp_pool.lp_token_supply, MINIMUM_LIQUIDITY);
+        assert_eq!(lp_pool.token_reserve, 1000);
+        assert_eq!(lp_pool.remove_liquidity(1), Err(Errors::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_add_liquidity_large_values_use_integer_math() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        lp_pool.token_reserve = 4;
+        lp_pool.lp_token_supply = (1 << 54) + 3;
+
+        let float_minted = (1.0 * (lp_pool.lp_token_supply as f64 / 4.0)) as u64;
+        let minted = lp_pool.add_liquidity(1).unwrap();
+        assert_eq!(minted, 1 << 52);
+        assert_ne!(minted, float_minted);
+    }
+
+    #[test]
+    fn test_add_liquidity_mint_overflow_on_drained_pool() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(1, 1, 5, 1000)).unwrap();
+        lp_pool.add_liquidity(2000).unwrap();
+        lp_pool.swap(1990).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.add_liquidity((1 << 62) + 1), Err(Errors::Overflow));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_add_liquidity_both_ratio_bounds_overflow() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 5, 1000), 10, u64::MAX / 2, 2000).unwrap();
+        assert_eq!(lp_pool.add_liquidity_both(100, u64::MAX / 4), Err(Errors::Overflow));
+    }
+
+    #[test]
+    fn test_add_liquidity_overflow() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        lp_pool.token_reserve = u64::MAX - 5;
+        lp_pool.lp_token_supply = 100;
+
+        assert_eq!(lp_pool.add_liquidity(10), Err(Errors::Overflow));
+        assert_eq!(lp_pool.token_reserve, u64::MAX - 5);
+        assert_eq!(lp_pool.lp_token_supply, 100);
+    }
+
+    #[test]
+    fn test_add_liquidity_saturating_caps_reserve() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        lp_pool.token_reserve = u64::MAX - 5;
+        lp_pool.lp_token_supply = u64::MAX / 4;
+
+        assert_eq!(lp_pool.add_liquidity_saturating(10), Ok(1));
+        assert_eq!(lp_pool.token_reserve, u64::MAX);
+        assert_eq!(lp_pool.lp_token_supply, u64::MAX / 4 + 1);
+    }
+
+    #[test]
+    fn test_add_liquidity_saturating_refuses_deposit_minting_nothing() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        lp_pool.token_reserve = u64::MAX - 5;
+        lp_pool.lp_token_supply = 100;
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.add_liquidity_saturating(10), Err(Errors::DepositMintsNothing));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_add_liquidity_saturating_matches_checked_without_overflow() {
+        let mut checked_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        let mut saturating_pool = checked_pool.clone();
+
+        assert_eq!(checked_pool.add_liquidity(2000), saturating_pool.add_liquidity_saturating(2000));
+        assert_eq!(checked_pool.add_liquidity(3000), saturating_pool.add_liquidity_saturating(3000));
+        assert_eq!(checked_pool, saturating_pool);
+    }
+
+    // add_liquidity_signed
+
+    #[test]
+    fn test_add_liquidity_signed() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        assert_eq!(lp_pool.add_liquidity_signed(-2000), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.add_liquidity_signed(0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.token_reserve, 0);
+
+        assert_eq!(lp_pool.add_liquidity_signed(2000), Ok(1000));
+        assert_eq!(lp_pool.token_reserve, 2000);
+    }
+
+    // add_liquidity_capped
+
+    #[test]
+    fn test_add_liquidity_capped_fully_accepted() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        assert_eq!(lp_pool.add_liquidity_capped(2000, 5000), Ok((1000, 0)));
+        assert_eq!(lp_pool.add_liquidity_capped(3000, 5000), Ok((3000, 0)));
+        assert_eq!(lp_pool.token_reserve, 5000);
+    }
+
+    #[test]
+    fn test_add_liquidity_capped_partially_accepted() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 5, 10, 1000)).unwrap();
+        lp_pool.add_liquidity(2000).unwrap();
+
+        assert_eq!(lp_pool.add_liquidity_capped(5000, 5000), Ok((3000, 2000)));
+        assert_eq!(lp_pool.token_reserve, 5000);
+        assert_eq!(lp_pool.add_liquidity_capped(100, 5000), Ok((0, 100)));
+        assert_eq!(lp_pool.token_reserve, 5000);
+    }
+
+    // add_liquidity_both
+
+    #[test]
+    fn test_add_liquidity_both_balanced() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 100, 1000).unwrap();
+        assert_eq!(lp_pool.add_liquidity_both(100, 10), Ok(100));
+        assert_eq!(lp_pool.token_reserve, 1100);
+        assert_eq!(lp_pool.staked_token_reserve, 110);
+        assert_eq!(lp_pool.lp_token_supply, 1100);
+    }
+
+    #[test]
+    fn test_add_liquidity_both_within_rounding() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 100, 1000).unwrap();
+        assert_eq!(lp_pool.add_liquidity_both(105, 10), Ok(105));
+
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 100, 1000).unwrap();
+        assert_eq!(lp_pool.add_liquidity_both(105, 11), Ok(105));
+    }
+
+    #[test]
+    fn test_add_liquidity_both_imbalanced() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 100, 1000).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.add_liquidity_both(100, 20), Err(Errors::ImbalancedDeposit));
+        assert_eq!(lp_pool.add_liquidity_both(100, 0), Err(Errors::ImbalancedDeposit));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_add_liquidity_both_first_deposit() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.add_liquidity_both(1000, 100), Ok(2000 - MINIMUM_LIQUIDITY));
+        assert_eq!(lp_pool.lp_token_supply, 2000);
+        assert_eq!(lp_pool.locked_lp_supply, MINIMUM_LIQUIDITY);
+        assert_eq!(lp_pool.token_reserve, 1000);
+        assert_eq!(lp_pool.staked_token_reserve, 100);
+    }
+
+    #[test]
+    fn test_add_liquidity_both_minting_nothing() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 100, 100).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.add_liquidity_both(5, 0), Err(Errors::DepositMintsNothing));
+        assert_eq!(lp_pool, before);
+        assert_eq!(lp_pool.add_liquidity_both(10, 1), Ok(1));
+    }
+
+    #[test]
+    fn test_add_liquidity_both_mint_overflow() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 0, 1, 2000).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.add_liquidity_both(0, 1 << 62), Err(Errors::Overflow));
+        assert_eq!(lp_pool, before);
+        assert_eq!(lp_pool.add_liquidity_both(0, 1), Ok(2000));
+    }
+
+    #[test]
+    fn test_add_liquidity_both_zero_amounts() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.add_liquidity_both(0, 0), Err(Errors::PropertyMustBeGreaterThanZero));
+    }
+
+    #[test]
+    fn test_add_liquidity_both_token_only_matches_add_liquidity() {
+        let empty = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        let funded = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 3000, 0, 2000).unwrap();
+
+        for lp_pool in [empty, funded] {
+            for amount in [1, 7, 1001, 2500] {
+                let (mut single, mut both) = (lp_pool.clone(), lp_pool.clone());
+                assert_eq!(single.add_liquidity(amount), both.add_liquidity_both(amount, 0));
+                assert_eq!(single, both);
+            }
+        }
+    }
+
+    // remove_liquidity
+
+    #[test]
+    fn test_remove_liquidity_successful() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 200;
+        lp_pool.staked_token_reserve = 300;
+        lp_pool.lp_token_supply = 500;
+        let result = lp_pool.remove_liquidity(100).unwrap();
+        assert_eq!(result, (40, 60));
+        assert_eq!(lp_pool.token_reserve, 160);
+        assert_eq!(lp_pool.staked_token_reserve, 240);
+        assert_eq!(lp_pool.lp_token_supply, 400);
+    }
+
+    #[test]
+    fn test_remove_zero_lp_tokens() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        let result = lp_pool.remove_liquidity(0);
+        assert_eq!(result, Err(Errors::PropertyMustBeGreaterThanZero));
+    }
+
+    #[test]
+    fn test_remove_insufficient_lp_tokens() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.lp_token_supply = 500;
+        let result = lp_pool.remove_liquidity(600);
+        assert_eq!(result, Err(Errors::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_remove_with_zero_reserves() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        let result = lp_pool.remove_liquidity(100);
+        assert_eq!(result, Err(Errors::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_remove_partial_tokens() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 200;
+        lp_pool.staked_token_reserve = 300;
+        lp_pool.lp_token_supply = 500;
+        let result = lp_pool.remove_liquidity(50).unwrap();
+        assert_eq!(result, (20, 30));
+        assert_eq!(lp_pool.token_reserve, 180);
+        assert_eq!(lp_pool.staked_token_reserve, 270);
+        assert_eq!(lp_pool.lp_token_supply, 450);
+    }
+
+    #[test]
+    fn test_remove_full_supply() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 200;
+        lp_pool.staked_token_reserve = 300;
+        lp_pool.lp_token_supply = 500;
+        let result = lp_pool.remove_liquidity(500).unwrap();
+        assert_eq!(result, (200, 300));
+        assert_eq!(lp_pool.token_reserve, 0);
+        assert_eq!(lp_pool.staked_token_reserve, 0);
+        assert_eq!(lp_pool.lp_token_supply, 0);
+    }
+
+    #[test]
+    fn test_remove_liquidity_large_values_use_integer_math() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = (1 << 54) + 3;
+        lp_pool.staked_token_reserve = (1 << 54) + 3;
+        lp_pool.lp_token_supply = 4;
+
+        let float_amount = ((lp_pool.token_reserve as f64) / 4.0) as u64;
+        let result = lp_pool.remove_liquidity(1).unwrap();
+        assert_eq!(result, (1 << 52, 1 << 52));
+        assert_ne!(result.0, float_amount);
+    }
+
+    #[test]
+    fn test_remove_liquidity_rounds_to_zero() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 9, 1000), 1_000, 1_000, 1_000_000_000).unwrap();
+        assert_eq!(lp_pool.remove_liquidity(1), Err(Errors::WithdrawalRoundsToZero));
+        assert_eq!(lp_pool.lp_token_supply, 1_000_000_000);
+        assert_eq!(lp_pool.stats().removes, 0);
+    }
+
+    #[test]
+    fn test_remove_liquidity_small_pool_drift_within_bound() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 1500, 5, 2000).unwrap();
+        assert_eq!(lp_pool.remove_liquidity(1000), Ok((750, 2)));
+        assert_eq!((lp_pool.token_reserve, lp_pool.staked_token_reserve), (750, 3));
+
+        // 1500 / 5 = 300 became 750 / 3 = 250, still within what rounding explains.
+        assert!(ratio_drift_within_rounding(1500, 5, 750, 3));
+        assert!(!ratio_drift_within_rounding(1500, 5, 750, 6));
+    }
+
+    #[test]
+    fn test_remove_liquidity_fair_ignores_ceil_rounding() {
+        let mut config = PoolConfig::new(10, 1, 2, 1000);
+        config.rounding = RoundingMode::Ceil;
+        let mut fair = LpPool::init_with_reserves(config, 1001, 101, 1000).unwrap();
+        let mut ceil = fair.clone();
+
+        assert_eq!(ceil.remove_liquidity(3), Ok((4, 1)));
+        assert_eq!(fair.remove_liquidity_fair(3), Ok((3, 0)));
+        assert_eq!(fair.remove_liquidity_fair(1), Ok((1, 0)));
+    }
+
+    #[test]
+    fn test_remove_liquidity_fair_dust_skimming_loop() {
+        let mut config = PoolConfig::new(10, 1, 2, 1000);
+        config.rounding = RoundingMode::Ceil;
+        let mut lp_pool = LpPool::init_with_reserves(config, 1001, 101, 1000).unwrap();
+
+        for _ in 0..50 {
+            let (value_before, supply_before) = (lp_pool.total_value_locked(), lp_pool.lp_token_supply as u128);
+            let minted = lp_pool.add_liquidity(3).unwrap();
+            let _ = lp_pool.remove_liquidity_fair(minted);
+
+            let (value_after, supply_after) = (lp_pool.total_value_locked(), lp_pool.lp_token_supply as u128);
+            assert!(value_after * supply_before >= value_before * supply_after);
+        }
+    }
+
+    #[test]
+    fn test_remove_liquidity_percent_half() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 200;
+        lp_pool.staked_token_reserve = 300;
+        lp_pool.lp_token_supply = 500;
+        let result = lp_pool.remove_liquidity_percent(100, 50).unwrap();
+        assert_eq!(result, (20, 30));
+        assert_eq!(lp_pool.lp_token_supply, 450);
+    }
+
+    #[test]
+    fn test_remove_liquidity_percent_full() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 200;
+        lp_pool.staked_token_reserve = 300;
+        lp_pool.lp_token_supply = 500;
+        let result = lp_pool.remove_liquidity_percent(500, 100).unwrap();
+        assert_eq!(result, (200, 300));
+        assert_eq!(lp_pool.lp_token_supply, 0);
+    }
+
+    #[test]
+    fn test_remove_liquidity_percent_above_100() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 200;
+        lp_pool.staked_token_reserve = 300;
+        lp_pool.lp_token_supply = 500;
+        assert_eq!(lp_pool.remove_liquidity_percent(100, 101), Err(Errors::PercentOutOfRange));
+        assert_eq!(lp_pool.lp_token_supply, 500);
+    }
+
+    #[test]
+    fn test_swap_overflow() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(2, 1, 2, u64::MAX)).unwrap();
+        lp_pool.token_reserve = u64::MAX;
+
+        assert_eq!(lp_pool.swap(u64::MAX / 2 + 1), Err(Errors::Overflow));
+        assert_eq!(lp_pool.token_reserve, u64::MAX);
+        assert_eq!(lp_pool.staked_token_reserve, 0);
+    }
+
+    #[test]
+    fn test_swap_staked_reserve_overflow() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 2, 1000), 1000, u64::MAX, 1000).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap(1), Err(Errors::Overflow));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_swap_saturating_caps_output() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(2, 1, 2, u64::MAX)).unwrap();
+        lp_pool.token_reserve = u64::MAX;
+        lp_pool.staked_token_reserve = u64::MAX - 1;
+
+        let result = lp_pool.swap_saturating(u64::MAX / 2 + 1).unwrap();
+        assert_eq!(result, u64::MAX - u64::MAX / 50);
+        assert_eq!(lp_pool.token_reserve, 0);
+        assert_eq!(lp_pool.staked_token_reserve, u64::MAX);
+    }
+
+    #[test]
+    fn test_swap_saturating_matches_checked_without_overflow() {
+        let mut checked_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        checked_pool.token_reserve = 1000;
+        let mut saturating_pool = checked_pool.clone();
+
+        assert_eq!(checked_pool.swap(5), saturating_pool.swap_saturating(5));
+        assert_eq!(checked_pool, saturating_pool);
+    }
+
+    // overflow_policy
+
+    fn pool_with_overflow_policy(overflow_policy: OverflowPolicy) -> LpPool {
+        let mut config = PoolConfig::new(1, 1, 2, u64::MAX);
+        config.overflow_policy = overflow_policy;
+        LpPool::init_with_reserves(config, u64::MAX - 5, u64::MAX - 1, 100).unwrap()
+    }
+
+    #[test]
+    fn test_overflow_policy_checked() {
+        let mut lp_pool = pool_with_overflow_policy(OverflowPolicy::Checked);
+        assert_eq!(lp_pool.config.overflow_policy, OverflowPolicy::default());
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.add_liquidity(10), Err(Errors::Overflow));
+        assert_eq!(lp_pool.swap(5), Err(Errors::Overflow));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_overflow_policy_saturating() {
+        let mut lp_pool = pool_with_overflow_policy(OverflowPolicy::Saturating);
+
+        let mut widely_held = lp_pool.clone();
+        widely_held.lp_token_supply = u64::MAX / 4;
+        assert_eq!(widely_held.add_liquidity(10), Ok(1));
+        assert_eq!(widely_held.token_reserve, u64::MAX);
+
+        assert_eq!(lp_pool.swap(5), Ok(5));
+        assert_eq!(lp_pool.staked_token_reserve, u64::MAX);
+        assert_eq!(lp_pool.token_reserve, u64::MAX - 10);
+
+        assert_eq!(lp_pool.remove_liquidity(100).map(|(token_amount, _)| token_amount), Ok(u64::MAX - 10));
+    }
+
+    // log
+
+    #[cfg(feature = "log")]
+    #[derive(Clone)]
+    struct SharedBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    #[cfg(feature = "log")]
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_writer_emits_json_for_swap() {
+        let buffer = SharedBuffer(Arc::new(std::sync::Mutex::new(Vec::new())));
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        lp_pool.set_log_writer(Box::new(buffer.clone()));
+
+        lp_pool.swap(10).unwrap();
+        assert!(lp_pool.swap(0).is_err());
+        lp_pool.marginal_price_after(10).unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 1);
+
+        let entry: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(entry["op"], "swap");
+        assert_eq!(entry["staked_token_amount"], 10);
+        assert_eq!(entry["token_amount"], 100);
+        assert_eq!(entry["fee"], 5);
+        assert_eq!(entry["token_out"], 95);
+        assert_eq!(entry["token_reserve"], 900);
+        assert_eq!(entry["staked_token_reserve"], 10);
+        assert_eq!(entry["lp_token_supply"], 1000);
+    }
+
+    #[cfg(feature = "log")]
+    fn logged_ops(buffer: &SharedBuffer) -> Vec<serde_json::Value> {
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        output.lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_writer_survives_rollback() {
+        let buffer = SharedBuffer(Arc::new(std::sync::Mutex::new(Vec::new())));
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.set_log_writer(Box::new(buffer.clone()));
+
+        assert!(lp_pool.transaction(|pool| pool.swap(1).map(|_| ())).is_err());
+        lp_pool.add_liquidity(2000).unwrap();
+        lp_pool.remove_liquidity(500).unwrap();
+
+        let ops = logged_ops(&buffer);
+        assert_eq!(ops.len(), 2);
+        assert_eq!(ops[0]["op"], "add_liquidity");
+        assert_eq!(ops[0]["lp_minted"], 1000);
+        assert_eq!(ops[1]["op"], "remove_liquidity");
+        assert_eq!(ops[1]["token_amount"], 500);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_writer_skips_rolled_back_operations() {
+        let buffer = SharedBuffer(Arc::new(std::sync::Mutex::new(Vec::new())));
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        lp_pool.set_log_writer(Box::new(buffer.clone()));
+
+        let result = lp_pool.transaction(|pool| {
+            pool.swap(10)?;
+            pool.swap(1000).map(|_| ())
+        });
+        assert_eq!(result, Err(Errors::InsufficientLiquidity));
+        assert!(lp_pool.swap_sliced(1000, 4).is_err());
+        assert!(logged_ops(&buffer).is_empty());
+
+        lp_pool.transaction(|pool| {
+            pool.transaction(|pool| pool.swap(10).map(|_| ()))?;
+            pool.swap(10).map(|_| ())
+        }).unwrap();
+        lp_pool.swap_sliced(10, 5).unwrap();
+
+        let ops = logged_ops(&buffer);
+        assert_eq!(ops.len(), 3);
+        assert_eq!((&ops[0]["op"], &ops[0]["token_reserve"]), (&"swap".into(), &900.into()));
+        assert_eq!((&ops[1]["op"], &ops[1]["token_reserve"]), (&"swap".into(), &800.into()));
+        assert_eq!(ops[2]["op"], "swap_sliced");
+        assert_eq!(ops[2]["slices"], 5);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_log_writer_covers_every_mutation() {
+        let buffer = SharedBuffer(Arc::new(std::sync::Mutex::new(Vec::new())));
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 100, 2000).unwrap();
+        lp_pool.set_log_writer(Box::new(buffer.clone()));
+
+        lp_pool.update_price(12).unwrap();
+        lp_pool.accrue_staked(10).unwrap();
+        lp_pool.merge(LpPool::init_with_reserves(PoolConfig::new(12, 1, 5, 1000), 10, 0, 10).unwrap()).unwrap();
+        lp_pool.set_fees(2, 4).unwrap();
+        lp_pool.collect_fees();
+
+        let ops: Vec<_> = logged_ops(&buffer).iter().map(|entry| entry["op"].as_str().unwrap().to_string()).collect();
+        assert_eq!(ops, ["update_price", "accrue_staked", "merge", "set_fees", "collect_fees"]);
+    }
+
+    // SharedLpPool
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_shared_pool_concurrent_swaps() {
+        let shared = SharedLpPool::new(LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 10_000), 10_000, 0, 10_000).unwrap());
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let shared = shared.clone();
+                std::thread::spawn(move || (0..25).map(|_| shared.swap(2).unwrap()).sum::<u64>())
+            })
+            .collect();
+        let total_out: u64 = handles.into_iter().map(|handle| handle.join().unwrap()).sum();
+
+        let lp_pool = shared.snapshot().unwrap();
+        assert_eq!(lp_pool.token_reserve, 10_000 - 200 * 20);
+        assert_eq!(lp_pool.staked_token_reserve, 200 * 2);
+        assert_eq!(total_out + lp_pool.collected_fees(), 200 * 20);
+        assert_eq!(lp_pool.stats().swaps, 200);
+        assert_eq!(shared.total_value_locked(), Ok(lp_pool.total_value_locked()));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_shared_pool_delegates() {
+        let shared = SharedLpPool::new(LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap());
+        assert_eq!(shared.add_liquidity(2000), Ok(1000));
+        assert_eq!(shared.calculate_fee_percentage(), Ok(5));
+        assert_eq!(shared.remove_liquidity(500), Ok((500, 0)));
+        assert_eq!(shared.lp_token_price(), Ok(Some(1.0)));
+        assert_eq!(shared.read(|lp_pool| lp_pool.lp_token_supply), Ok(1500));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_shared_pool_poisoned_by_panicking_writer() {
+        let shared = SharedLpPool::new(LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap());
+
+        let writer = shared.clone();
+        let panicked = std::thread::spawn(move || {
+            let _ = writer.write(|lp_pool| {
+                lp_pool.token_reserve = 0;
+                panic!("writer failed halfway");
+            });
+        })
+        .join();
+        assert!(panicked.is_err());
+
+        assert_eq!(shared.snapshot(), Err(Errors::PoolPoisoned));
+        assert_eq!(shared.swap(10), Err(Errors::PoolPoisoned));
+        assert_eq!(shared.calculate_fee_percentage(), Err(Errors::PoolPoisoned));
+    }
+
+    // price oracle
+
+    struct MockOracle(Arc<core::sync::atomic::AtomicU64>);
+
+    impl PriceOracle for MockOracle {
+        fn price(&self) -> u64 {
+            self.0.load(core::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[test]
+    fn test_swap_uses_live_oracle_price() {
+        let live_price = Arc::new(core::sync::atomic::AtomicU64::new(10));
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, u64::MAX), 10_000, 0, 10_000).unwrap();
+        lp_pool.set_price_oracle(Box::new(MockOracle(live_price.clone())));
+
+        assert_eq!(lp_pool.swap(10), Ok(100 - 1));
+        live_price.store(20, core::sync::atomic::Ordering::SeqCst);
+        assert_eq!(lp_pool.swap(10), Ok(200 - 2));
+        assert_eq!(lp_pool.token_reserve, 10_000 - 300);
+    }
+
+    #[test]
+    fn test_fixed_price_oracle_and_clear() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, u64::MAX), 10_000, 0, 10_000).unwrap();
+        lp_pool.set_price_oracle(Box::new(FixedPrice(5)));
+        assert_eq!(lp_pool.swap(10), Ok(49));
+        assert_eq!(lp_pool.config().price, 100);
+
+        lp_pool.clear_price_oracle();
+        assert_eq!(lp_pool.swap(10), Ok(1000 - 10));
+    }
+
+    #[test]
+    fn test_swap_rejects_zero_oracle_price() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, u64::MAX), 10_000, 0, 10_000).unwrap();
+        lp_pool.set_price_oracle(Box::new(FixedPrice(0)));
+        assert_eq!(lp_pool.swap(10), Err(Errors::PropertyMustBeGreaterThanZero));
+    }
+
+    // swap guard
+
+    #[test]
+    fn test_swap_guard_blocks_large_swaps() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        lp_pool.set_swap_guard(Box::new(|staked_token_amount| staked_token_amount <= 100));
+
+        assert!(lp_pool.swap(50).is_ok());
+        let before = lp_pool.clone();
+        assert_eq!(lp_pool.swap(150), Err(Errors::SwapRejectedByPolicy));
+        assert_eq!(lp_pool, before);
+
+        lp_pool.clear_swap_guard();
+        assert!(lp_pool.swap(150).is_ok());
+    }
+
+    #[test]
+    fn test_swap_guard_shared_by_clones() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        lp_pool.set_swap_guard(Box::new(|_| false));
+
+        let cloned = lp_pool.clone();
+        assert_eq!(cloned, lp_pool);
+        assert!(!lp_pool.can_swap(1));
+    }
+
+    #[test]
+    fn test_swap_guard_applies_to_every_variant() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        lp_pool.set_swap_guard(Box::new(|_| false));
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap_no_fee(10), Err(Errors::SwapRejectedByPolicy));
+        assert_eq!(lp_pool.swap_with_fee_override(10, 100), Err(Errors::SwapRejectedByPolicy));
+        assert_eq!(lp_pool.swap_tiered(10, &[(0, 1)]), Err(Errors::SwapRejectedByPolicy));
+        assert_eq!(lp_pool.swap_exact_out(10), Err(Errors::SwapRejectedByPolicy));
+        assert_eq!(lp_pool.swap_dynamic_fee(10), Err(Errors::SwapRejectedByPolicy));
+        assert_eq!(lp_pool.swap_saturating(10), Err(Errors::SwapRejectedByPolicy));
+        assert_eq!(lp_pool, before);
+    }
+
+    // paused
+
+    #[test]
+    fn test_paused_blocks_deposits_and_swaps() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.add_liquidity(2000).unwrap();
+        lp_pool.set_paused(true);
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.add_liquidity(1000), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.add_liquidity_saturating(1000), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.add_liquidity_both(100, 0), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap(10), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap_saturating(10), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap_exact_out(50), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap_no_fee(10), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap_with_fee_override(10, 100), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap_tiered(10, &[(0, 1)]), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap_dynamic_fee(10), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap_sliced(10, 2), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap_detailed(10), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.rebalance_to_ratio(50), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_paused_checked_before_swap_validation() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.add_liquidity(2000).unwrap();
+        lp_pool.set_paused(true);
+
+        assert_eq!(lp_pool.swap(0), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap(1_000_000), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap_exact_out(1_000_000), Err(Errors::PoolPaused));
+        assert_eq!(lp_pool.swap_saturating(u64::MAX), Err(Errors::PoolPaused));
+    }
+
+    #[test]
+    fn test_paused_allows_withdrawals() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.add_liquidity(2000).unwrap();
+        lp_pool.set_paused(true);
+
+        assert_eq!(lp_pool.remove_liquidity(500), Ok((500, 0)));
+        assert_eq!(lp_pool.remove_liquidity_percent(500, 100), Ok((500, 0)));
+
+        lp_pool.set_paused(false);
+        assert!(lp_pool.add_liquidity(1000).is_ok());
+        assert!(lp_pool.swap(10).is_ok());
+    }
+
+    // dry_run
+
+    #[test]
+    fn test_dry_run_swap_leaves_reserves() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let before = lp_pool.clone();
+
+        lp_pool.set_dry_run(true);
+        assert_eq!(lp_pool.swap(10), Ok(95));
+        assert_eq!(lp_pool.swap(10), Ok(95));
+        assert_eq!(lp_pool.swap(0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.token_reserve, 1000);
+        assert_eq!(lp_pool.staked_token_reserve, 0);
+        assert_eq!(lp_pool.stats(), Stats::default());
+
+        lp_pool.set_dry_run(false);
+        assert_eq!(lp_pool, before);
+        assert_eq!(lp_pool.swap(10), Ok(95));
+        assert_eq!(lp_pool.token_reserve, 900);
+    }
+
+    #[test]
+    fn test_dry_run_liquidity_leaves_pool() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.add_liquidity(2000).unwrap();
+        lp_pool.set_dry_run(true);
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.add_liquidity(3000), Ok(3000));
+        assert_eq!(lp_pool.remove_liquidity(500), Ok((500, 0)));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_dry_run_leaves_state_unchanged_for_every_mutator() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 100, 2000).unwrap();
+        lp_pool.swap(10).unwrap();
+        let mut live = lp_pool.clone();
+        lp_pool.set_dry_run(true);
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.add_liquidity_saturating(500), live.clone().add_liquidity_saturating(500));
+        assert!(lp_pool.add_liquidity_both(90, 11).is_ok());
+        assert_eq!(lp_pool.add_liquidity_both(90, 11), live.clone().add_liquidity_both(90, 11));
+        assert_eq!(lp_pool.swap_exact_out(50), live.clone().swap_exact_out(50));
+        assert_eq!(lp_pool.swap_no_fee(10), live.clone().swap_no_fee(10));
+        assert_eq!(lp_pool.swap_with_fee_override(10, 100), live.clone().swap_with_fee_override(10, 100));
+        assert_eq!(lp_pool.swap_tiered(10, &[(0, 2)]), live.clone().swap_tiered(10, &[(0, 2)]));
+        assert_eq!(lp_pool.swap_dynamic_fee(10), live.clone().swap_dynamic_fee(10));
+        assert_eq!(lp_pool.swap_saturating(10), live.clone().swap_saturating(10));
+        assert_eq!(lp_pool.swap_max_fee(10, 5), live.clone().swap_max_fee(10, 5));
+        assert_eq!(lp_pool.swap_batch(&[10, 20]), live.clone().swap_batch(&[10, 20]));
+        assert_eq!(lp_pool.swap_sliced(30, 3), live.clone().swap_sliced(30, 3));
+        assert_eq!(lp_pool.swap_detailed(10), live.clone().swap_detailed(10));
+        assert_eq!(lp_pool.rebalance_to_ratio(50), live.clone().rebalance_to_ratio(50));
+        assert_eq!(lp_pool.accrue_staked(5), live.clone().accrue_staked(5));
+        assert_eq!(lp_pool.update_price(12), live.clone().update_price(12));
+        assert_eq!(lp_pool.merge(live.clone()), live.clone().merge(live.clone()));
+        assert_eq!(lp_pool.collect_fees(), live.collect_fees());
+        assert_eq!(lp_pool, before);
+    }
+
+    // swap_max_fee
+
+    #[test]
+    fn test_swap_max_fee_under_tolerance() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.swap_max_fee(10, 5), Ok(95));
+        assert_eq!(lp_pool.token_reserve, 900);
+    }
+
+    #[test]
+    fn test_swap_max_fee_over_tolerance() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap_max_fee(10, 4), Err(Errors::FeeTooHigh));
+        assert_eq!(lp_pool, before);
+    }
+
+    // swap_exact_out
+
+    #[test]
+    fn test_swap_exact_out_feasible() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let mut one_less = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap_exact_out(100), Ok((11, 4)));
+        assert_eq!(lp_pool.token_reserve, 1000 - 110);
+        assert_eq!(lp_pool.staked_token_reserve, 11);
+        assert_eq!(lp_pool.collected_fees(), 10);
+        assert_eq!(one_less.swap(10), Ok(95));
+    }
+
+    #[test]
+    fn test_swap_exact_out_reports_dust() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let fee = swap_fee_amount(100, lp_pool.calculate_fee_percentage());
+
+        let (staked_token_amount, dust) = lp_pool.swap_exact_out(93).unwrap();
+        assert_eq!(staked_token_amount, 10);
+        assert_eq!(dust, 10 * 10 - 93 - fee);
+        assert_eq!(dust, 2);
+        assert_eq!(lp_pool.token_reserve, 1000 - 100);
+        assert_eq!(lp_pool.collected_fees(), fee + dust);
+    }
+
+    #[test]
+    fn test_swap_exact_out_dust_is_not_the_truncated_remainder() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+
+        // 93 out plus the fee needs 98 tokens. Truncating 98 / 10 would leave 98 % 10 = 8 as dust
+        // and only pay 90 - 5 = 85; rounding up to 10 staked overpays 100 - 98 = 2 instead.
+        assert_eq!(swap_fee_amount(100, lp_pool.calculate_fee_percentage()), 5);
+        assert_eq!(lp_pool.clone().swap_exact_out(93), Ok((10, 2)));
+        assert_eq!(lp_pool.clone().swap(9), Ok(85));
+
+        // 95 out plus the fee is exactly 10 staked units, so nothing is overpaid.
+        assert_eq!(lp_pool.clone().swap_exact_out(95), Ok((10, 0)));
+    }
+
+    #[test]
+    fn test_swap_exact_out_exceeds_reserve() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap_exact_out(1001), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.swap_exact_out(1000), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.swap_exact_out(0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool, before);
+    }
+
+    // swap_detailed
+
+    #[test]
+    fn test_swap_detailed_reports_reserve_deltas() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.swap_detailed(10), Ok((95, -100, 10)));
+        assert_eq!(lp_pool.token_reserve, 900);
+        assert_eq!(lp_pool.staked_token_reserve, 10);
+    }
+
+    #[test]
+    fn test_swap_detailed_delta_overflow() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 2, u64::MAX), u64::MAX, 0, 1).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap_detailed(u64::MAX), Err(Errors::Overflow));
+        assert_eq!(lp_pool, before);
+    }
+
+    // swap_breakdown
+
+    #[test]
+    fn test_swap_breakdown_matches_swap() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 800, 0, 800).unwrap();
+        let (token_amount, fee, fee_percentage) = lp_pool.swap_breakdown(30).unwrap();
+
+        assert_eq!((token_amount, fee, fee_percentage), (300, 12, 4));
+        assert_eq!(fee_percentage, lp_pool.calculate_fee_percentage());
+        assert_eq!(lp_pool.token_reserve, 800);
+        assert_eq!(lp_pool.swap(30), Ok(token_amount - fee));
+    }
+
+    #[test]
+    fn test_swap_breakdown_errors() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 800, 0, 800).unwrap();
+        assert_eq!(lp_pool.swap_breakdown(0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.swap_breakdown(81), Err(Errors::InsufficientLiquidity));
+    }
+
+    // effective_swap_price
+
+    #[test]
+    fn test_effective_swap_price_below_nominal_by_fee() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        let fee_percentage = lp_pool.calculate_fee_percentage();
+
+        let effective_price = lp_pool.effective_swap_price(10).unwrap();
+        assert_eq!(effective_price, 98.0);
+        assert_eq!(effective_price, 100.0 * (1.0 - fee_percentage as f64 / 100.0));
+        assert_eq!(lp_pool.token_reserve, 1000);
+    }
+
+    #[test]
+    fn test_effective_swap_price_errors() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.effective_swap_price(0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.effective_swap_price(10), Err(Errors::InsufficientLiquidity));
+    }
+
+    // swap yielding nothing
+
+    #[test]
+    fn test_swap_yields_nothing() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 60, 1000), 850, 0, 1000).unwrap();
+        assert_eq!(lp_pool.calculate_fee_percentage(), 51);
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap(1), Err(Errors::SwapYieldsNothing));
+        assert_eq!(lp_pool.swap_saturating(1), Err(Errors::SwapYieldsNothing));
+        assert_eq!(lp_pool, before);
+        assert_eq!(lp_pool.swap(2), Ok(1));
+    }
+
+    // can_swap
+
+    #[test]
+    fn test_can_swap() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert!(lp_pool.can_swap(50));
+        assert!(!lp_pool.can_swap(101));
+        assert!(!lp_pool.can_swap(0));
+        assert_eq!(lp_pool.token_reserve, 1000);
+    }
+
+    #[test]
+    fn test_can_swap_respects_pool_limits() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        lp_pool.config.min_swap_amount = Some(5);
+        lp_pool.config.reserve_floor = 500;
+        assert!(!lp_pool.can_swap(4));
+        assert!(lp_pool.can_swap(5));
+        assert!(!lp_pool.can_swap(60));
+    }
+
+    // is_arbitrage_open
+
+    #[test]
+    fn test_is_arbitrage_open_aligned_price() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.effective_swap_price(1), Ok(98.0));
+        assert!(!lp_pool.is_arbitrage_open(98, 1));
+        assert!(!lp_pool.is_arbitrage_open(100, 5));
+    }
+
+    #[test]
+    fn test_is_arbitrage_open_divergent_price() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 1000, 0, 1000).unwrap();
+        assert!(lp_pool.is_arbitrage_open(120, 5));
+        assert!(lp_pool.is_arbitrage_open(80, 5));
+    }
+
+    #[test]
+    fn test_is_arbitrage_open_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert!(!lp_pool.is_arbitrage_open(1, 0));
+    }
+
+    // swap_batch
+
+    #[test]
+    fn test_swap_batch_succeeds_fully() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+
+        let results = lp_pool.swap_batch(&[2, 3, 5]);
+        assert_eq!(results, vec![Ok(196), Ok(297), Ok(495)]);
+        assert_eq!(lp_pool.token_reserve, 0);
+        assert_eq!(lp_pool.staked_token_reserve, 10);
+    }
+
+    #[test]
+    fn test_swap_batch_halts_on_failure() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+
+        let results = lp_pool.swap_batch(&[4, 4, 4, 1]);
+        assert_eq!(results, vec![Ok(392), Ok(396), Err(Errors::InsufficientLiquidity)]);
+        assert_eq!(lp_pool.token_reserve, 200);
+        assert_eq!(lp_pool.staked_token_reserve, 8);
+    }
+
+    // realized_avg_fee_bps
+
+    #[test]
+    fn test_realized_avg_fee_without_swaps() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 1, 3, 1000)).unwrap();
+        assert_eq!(lp_pool.realized_avg_fee_bps(), 0);
+    }
+
+    #[test]
+    fn test_realized_avg_fee_across_reserve_levels() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 3, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+
+        assert_eq!(lp_pool.swap(2), Ok(194));
+        assert_eq!(lp_pool.realized_avg_fee_bps(), 300);
+
+        assert_eq!(lp_pool.swap(3), Ok(294));
+        assert_eq!(lp_pool.total_fees, 12);
+        assert_eq!(lp_pool.total_gross_swapped, 500);
+        assert_eq!(lp_pool.realized_avg_fee_bps(), 240);
+    }
+
+    #[test]
+    fn test_realized_avg_fee_ignores_failed_swaps() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 3, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+
+        lp_pool.swap(2).unwrap();
+        assert_eq!(lp_pool.swap(100), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.realized_avg_fee_bps(), 300);
+    }
+
+    // collected_fees
+
+    #[test]
+    fn test_collected_fees_sum_of_swaps() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 3, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+
+        let (_, first_fee) = lp_pool.quote_swap(2).unwrap();
+        lp_pool.swap(2).unwrap();
+        let (_, second_fee) = lp_pool.quote_swap(3).unwrap();
+        lp_pool.swap(3).unwrap();
+
+        assert_eq!(lp_pool.collected_fees(), first_fee + second_fee);
+        assert_eq!(lp_pool.collected_fees(), 12);
+    }
+
+    #[test]
+    fn test_collect_fees_drains() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 3, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        lp_pool.swap(2).unwrap();
+
+        assert_eq!(lp_pool.collect_fees(), 6);
+        assert_eq!(lp_pool.collected_fees(), 0);
+        assert_eq!(lp_pool.collect_fees(), 0);
+        assert_eq!(lp_pool.realized_avg_fee_bps(), 300);
+    }
+
+    // swap_sliced
+
+    #[test]
+    fn test_swap_sliced_recalculates_fee_per_slice() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let mut single_pool = lp_pool.clone();
+
+        let sliced_out = lp_pool.swap_sliced(80, 4).unwrap();
+        let single_out = single_pool.swap(80).unwrap();
+
+        assert_eq!(single_out, 800 - 40);
+        assert_eq!(sliced_out, 800 - (10 + 8 + 6 + 4));
+        assert_ne!(sliced_out, single_out);
+        assert_eq!(lp_pool.token_reserve, single_pool.token_reserve);
+        assert_eq!(lp_pool.staked_token_reserve, 80);
+    }
+
+    #[test]
+    fn test_swap_sliced_remainder_goes_to_last_slice() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        lp_pool.swap_sliced(82, 4).unwrap();
+        assert_eq!(lp_pool.staked_token_reserve, 82);
+        assert_eq!(lp_pool.token_reserve, 180);
+    }
+
+    #[test]
+    fn test_swap_sliced_is_all_or_nothing() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap_sliced(120, 4), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_swap_sliced_invalid_slices() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.swap_sliced(80, 0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.swap_sliced(3, 4), Err(Errors::PropertyMustBeGreaterThanZero));
+    }
+
+    // stats
+
+    #[test]
+    fn test_stats_count_only_successful_operations() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.stats(), Stats::default());
+
+        assert!(lp_pool.add_liquidity(500).is_err());
+        lp_pool.add_liquidity(2000).unwrap();
+        lp_pool.add_liquidity(1000).unwrap();
+        assert!(lp_pool.swap(0).is_err());
+        assert!(lp_pool.swap(1000).is_err());
+        lp_pool.swap(10).unwrap();
+        assert!(lp_pool.remove_liquidity(0).is_err());
+        lp_pool.remove_liquidity(100).unwrap();
+
+        assert_eq!(lp_pool.stats(), Stats { adds: 2, removes: 1, swaps: 1 });
+    }
+
+    #[test]
+    fn test_stats_not_counted_for_rolled_back_transaction() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        let result = lp_pool.transaction(|pool| {
+            pool.add_liquidity(2000)?;
+            pool.swap(1000)?;
+            Ok(())
+        });
+
+        assert!(result.is_err());
+        assert_eq!(lp_pool.stats(), Stats::default());
+    }
+
+    // with_receipt
+
+    #[test]
+    fn test_with_receipt_sequence_numbers() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.next_sequence(), 1);
+
+        let added = lp_pool.with_receipt(|pool| pool.add_liquidity(2000)).unwrap();
+        assert_eq!(added, Receipt { sequence: Some(1), result: 1000 });
+
+        assert_eq!(lp_pool.with_receipt(|pool| pool.swap(1000)), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.next_sequence(), 2);
+
+        assert_eq!(lp_pool.with_receipt(|pool| pool.swap(10)).unwrap().sequence, Some(2));
+        assert_eq!(lp_pool.with_receipt(|pool| pool.remove_liquidity(100)).unwrap().sequence, Some(3));
+        assert_eq!(lp_pool.next_sequence(), 4);
+    }
+
+    #[test]
+    fn test_with_receipt_every_mutation_takes_a_number() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 100, 2000).unwrap();
+        lp_pool.treasury_balance = 5;
+
+        assert_eq!(lp_pool.with_receipt(|pool| pool.update_price(12)).unwrap().sequence, Some(1));
+        assert_eq!(lp_pool.with_receipt(|pool| pool.accrue_staked(10)).unwrap().sequence, Some(2));
+        assert_eq!(lp_pool.with_receipt(|pool| Ok(pool.collect_fees())).unwrap(), Receipt { sequence: Some(3), result: 5 });
+        assert_eq!(lp_pool.with_receipt(|pool| pool.rebalance_to_ratio(50)).unwrap().sequence, Some(4));
+
+        let other = LpPool::init_with_reserves(PoolConfig::new(12, 1, 5, 1000), 10, 0, 10).unwrap();
+        assert_eq!(lp_pool.with_receipt(|pool| pool.merge(other)).unwrap().sequence, Some(5));
+        assert_eq!(lp_pool.next_sequence(), 6);
+    }
+
+    #[test]
+    fn test_with_receipt_swap_sliced_takes_one_number() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+
+        assert_eq!(lp_pool.with_receipt(|pool| pool.swap_sliced(10, 5)).unwrap().sequence, Some(1));
+        assert_eq!(lp_pool.stats().swaps, 5);
+        assert_eq!(lp_pool.next_sequence(), 2);
+
+        assert!(lp_pool.with_receipt(|pool| pool.swap_sliced(1000, 5)).is_err());
+        assert_eq!(lp_pool.next_sequence(), 2);
+    }
+
+    #[test]
+    fn test_with_receipt_dry_run_issues_no_number() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.set_dry_run(true);
+
+        let added = lp_pool.with_receipt(|pool| pool.add_liquidity(2000)).unwrap();
+        assert_eq!(added, Receipt { sequence: None, result: 1000 });
+        assert_eq!(lp_pool.next_sequence(), 1);
+
+        lp_pool.set_dry_run(false);
+        assert_eq!(lp_pool.with_receipt(|pool| pool.add_liquidity(2000)).unwrap().sequence, Some(1));
+    }
+
+    // protocol_fee_share
+
+    fn pool_with_protocol_fee_share(protocol_fee_share: u64) -> LpPool {
+        let mut config = PoolConfig::new(10, 1, 5, 1000);
+        config.protocol_fee_share = protocol_fee_share;
+        LpPool::init_with_reserves(config, 1000, 0, 1000).unwrap()
+    }
+
+    #[test]
+    fn test_protocol_fee_share_splits_fee() {
+        let mut lp_pool = pool_with_protocol_fee_share(40);
+        assert_eq!(lp_pool.swap(10), Ok(95));
+        assert_eq!(lp_pool.treasury_balance(), 2);
+        assert_eq!(lp_pool.collected_fees(), 5);
+        assert_eq!(lp_pool.token_reserve, 1000 - 95 - 2);
+
+        assert_eq!(lp_pool.collect_fees(), 2);
+        assert_eq!(lp_pool.treasury_balance(), 0);
+        assert_eq!(lp_pool.collected_fees(), 0);
+        assert_eq!(lp_pool.token_reserve, 1000 - 95 - 2);
+    }
+
+    #[test]
+    fn test_protocol_fee_share_zero_leaves_fee_in_reserve() {
+        let mut lp_pool = pool_with_protocol_fee_share(0);
+        assert_eq!(lp_pool.swap(10), Ok(95));
+        assert_eq!(lp_pool.treasury_balance(), 0);
+        assert_eq!(lp_pool.collected_fees(), 5);
+        assert_eq!(lp_pool.token_reserve, 1000 - 95);
+        assert_eq!(lp_pool.realized_avg_fee_bps(), 500);
+    }
+
+    #[test]
+    fn test_protocol_fee_share_defaults_to_whole_fee() {
+        let mut lp_pool = pool_with_protocol_fee_share(PoolConfig::new(10, 1, 5, 1000).protocol_fee_share);
+        assert_eq!(lp_pool.swap(10), Ok(95));
+        assert_eq!(lp_pool.treasury_balance(), 5);
+        assert_eq!(lp_pool.token_reserve, 900);
+    }
+
+    #[test]
+    fn test_protocol_fee_share_above_100() {
+        let mut config = PoolConfig::new(10, 1, 5, 1000);
+        config.protocol_fee_share = 101;
+        assert_eq!(LpPool::init(config), Err(Errors::FeeTooLarge));
+    }
+
+    // swap_no_fee
+
+    #[test]
+    fn test_swap_no_fee_difference_is_the_fee() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        let mut no_fee_pool = lp_pool.clone();
+        let fee = lp_pool.quote_swap(10).unwrap().1;
+
+        let with_fee = lp_pool.swap(10).unwrap();
+        let without_fee = no_fee_pool.swap_no_fee(10).unwrap();
+
+        assert_eq!(without_fee, 1000);
+        assert_eq!(without_fee - with_fee, fee);
+        assert_eq!(no_fee_pool.token_reserve, lp_pool.token_reserve);
+        assert_eq!(no_fee_pool.staked_token_reserve, lp_pool.staked_token_reserve);
+    }
+
+    #[test]
+    fn test_swap_no_fee_keeps_validation() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.swap_no_fee(0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.swap_no_fee(10), Err(Errors::InsufficientLiquidity));
+    }
+
+    // swap_with_fee
+
+    #[test]
+    fn test_swap_with_fee_overrides() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.swap_with_fee(50, 0), Ok(500));
+        assert_eq!(lp_pool.swap_with_fee(50, 10), Ok(450));
+        assert_eq!(lp_pool.swap_with_fee(50, lp_pool.calculate_fee_percentage()), lp_pool.clone().swap(50));
+        assert_eq!(lp_pool.token_reserve, 1000);
+    }
+
+    #[test]
+    fn test_swap_with_fee_errors() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.swap_with_fee(50, 101), Err(Errors::FeeOutOfRange));
+        assert_eq!(Errors::FeeOutOfRange.to_string(), "Fee must be at most 100 percent");
+        assert_eq!(lp_pool.swap_with_fee(101, 0), Err(Errors::InsufficientLiquidity));
+    }
+
+    // swap_tiered
+
+    #[test]
+    fn test_swap_tiered_two_tiers() {
+        let schedule = [(0, 10), (50, 2)];
+
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 5, 1000), 400, 0, 400).unwrap();
+        assert_eq!(lp_pool.swap_tiered(100, &schedule), Ok(90));
+
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 5, 1000), 800, 0, 800).unwrap();
+        assert_eq!(lp_pool.swap_tiered(100, &schedule), Ok(98));
+        assert_eq!(lp_pool.total_fees, 2);
+    }
+
+    #[test]
+    fn test_swap_tiered_three_tiers() {
+        let schedule = [(75, 1), (25, 6), (50, 3)];
+        let pool_at = |token_reserve| LpPool::init_with_reserves(PoolConfig::new(1, 1, 5, 1000), token_reserve, 0, 1000).unwrap();
+
+        assert_eq!(pool_at(300).swap_tiered(100, &schedule), Ok(94));
+        assert_eq!(pool_at(600).swap_tiered(100, &schedule), Ok(97));
+        assert_eq!(pool_at(900).swap_tiered(100, &schedule), Ok(99));
+
+        let mut below_tiers = pool_at(200);
+        let curve_output = below_tiers.clone().swap(100);
+        assert_eq!(below_tiers.swap_tiered(100, &schedule), curve_output);
+    }
+
+    #[test]
+    fn test_swap_tiered_fee_out_of_range() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 5, 1000), 800, 0, 800).unwrap();
+        let before = lp_pool.clone();
+        assert_eq!(lp_pool.swap_tiered(100, &[(0, 101)]), Err(Errors::FeeOutOfRange));
+        assert_eq!(lp_pool, before);
+    }
+
+    // swap_with_fee_override
+
+    #[test]
+    fn test_swap_with_fee_override_below_curve() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        let mut curve_pool = lp_pool.clone();
+
+        let overridden = lp_pool.swap_with_fee_override(10, 50).unwrap();
+        let curve = curve_pool.swap(10).unwrap();
+
+        assert_eq!(overridden, 995);
+        assert!(overridden > curve);
+        assert_eq!(lp_pool.token_reserve, curve_pool.token_reserve);
+        assert_eq!(lp_pool.staked_token_reserve, curve_pool.staked_token_reserve);
+    }
+
+    #[test]
+    fn test_swap_with_fee_override_bounds() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+
+        assert_eq!(lp_pool.swap_with_fee_override(1, 201), Err(Errors::FeeTooLarge));
+        assert_eq!(lp_pool.token_reserve, 1000);
+        assert_eq!(lp_pool.swap_with_fee_override(1, 200), Ok(98));
+        assert_eq!(lp_pool.swap_with_fee_override(1, 0), Ok(100));
+    }
+
+    #[test]
+    fn test_swap_with_fee_override_rounds_half_unit_up() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+
+        assert_eq!(lp_pool.swap_with_fee_override(1, 50), Ok(100 - 1));
+        assert_eq!(lp_pool.swap_with_fee_override(1, 49), Ok(100));
+        assert_eq!(swap_fee_amount_bps(100, 50), 1);
+        assert_eq!(swap_fee_amount_bps(100, 149), 1);
+    }
+
+    // marginal_price_after
+
+    #[test]
+    fn test_marginal_price_after_tracks_fee_move() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 5, 10_000), 10_000, 0, 10_000).unwrap();
+        assert_eq!(lp_pool.effective_swap_price(1), Ok(95.0));
+
+        assert_eq!(lp_pool.marginal_price_after(1), Ok(96.0));
+        assert_eq!(lp_pool.marginal_price_after(75), Ok(98.0));
+        assert_eq!(lp_pool.token_reserve, 10_000);
+    }
+
+    #[test]
+    fn test_marginal_price_after_failed_swap() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 5, 10_000), 10_000, 0, 10_000).unwrap();
+        assert_eq!(lp_pool.marginal_price_after(101), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.marginal_price_after(100), Err(Errors::InsufficientLiquidity));
+    }
+
+    // fee_after_swap
+
+    #[test]
+    fn test_fee_after_swap_follows_drained_reserve() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.calculate_fee_percentage(), 5);
+
+        assert_eq!(lp_pool.fee_after_swap(50), Ok(3));
+        assert_eq!(lp_pool.token_reserve, 1000);
+        assert_eq!(lp_pool.stats().swaps, 0);
+    }
+
+    #[test]
+    fn test_fee_after_swap_failed_swap() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.fee_after_swap(101), Err(Errors::InsufficientLiquidity));
+    }
+
+    // price_impact
+
+    #[test]
+    fn test_price_impact_tiny_and_large_swaps() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 100_000), 10_000, 0, 10_000).unwrap();
+        assert_eq!(lp_pool.calculate_fee_percentage(), 1);
+
+        assert_eq!(lp_pool.price_impact(1), Ok(0.0));
+        let impact = lp_pool.price_impact(500).unwrap();
+        assert!((impact - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_price_impact_errors() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 100_000), 10_000, 0, 10_000).unwrap();
+        assert_eq!(lp_pool.price_impact(0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.price_impact(1001), Err(Errors::InsufficientLiquidity));
+    }
+
+    // swap_dynamic_fee
+
+    #[test]
+    fn test_swap_dynamic_fee_never_below_swap() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        let mut dynamic_pool = lp_pool.clone();
+
+        let static_out = lp_pool.swap(75).unwrap();
+        let dynamic_out = dynamic_pool.swap_dynamic_fee(75).unwrap();
+
+        assert_eq!(static_out, 750 - 38);
+        assert_eq!(dynamic_out, static_out);
+        assert_eq!(dynamic_pool.token_reserve, lp_pool.token_reserve);
+        assert_eq!(dynamic_pool.staked_token_reserve, lp_pool.staked_token_reserve);
+    }
+
+    #[test]
+    fn test_swap_dynamic_fee_charges_more_for_draining_swap() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.token_reserve = 500;
+        let mut dynamic_pool = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap(50), Ok(500 - 15));
+        assert_eq!(dynamic_pool.swap_dynamic_fee(50), Ok(500 - 25));
+        assert_eq!(dynamic_pool.total_fees, 25);
+    }
+
+    #[test]
+    fn test_swap_dynamic_fee_insufficient_liquidity() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        assert_eq!(lp_pool.swap_dynamic_fee(101), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.token_reserve, 1000);
+    }
+
+    #[test]
+    fn test_remove_liquidity_with_withdraw_fee() {
+        let mut config = PoolConfig::new(100, 1, 2, 1000);
+        config.withdraw_fee = 2;
+        let mut lp_pool = LpPool::init_with_reserves(config, 1000, 500, 2000).unwrap();
+
+        let result = lp_pool.remove_liquidity(1000).unwrap();
+        assert_eq!(result, (490, 245));
+        assert_eq!(lp_pool.token_reserve, 510);
+        assert_eq!(lp_pool.staked_token_reserve, 255);
+        assert_eq!(lp_pool.lp_token_supply, 1000);
+    }
+
+    #[test]
+    fn test_withdraw_fee_full_exit_keeps_backing_supply() {
+        let mut config = PoolConfig::new(100, 1, 2, 1000);
+        config.withdraw_fee = 2;
+        let mut lp_pool = LpPool::init_with_reserves(config, 1000, 500, 2000).unwrap();
+        assert_eq!(lp_pool.locked_lp_supply, MINIMUM_LIQUIDITY);
+
+        assert_eq!(lp_pool.remove_liquidity(1001), Err(Errors::InsufficientLiquidity));
+        lp_pool.remove_liquidity(600).unwrap();
+        lp_pool.remove_liquidity(400).unwrap();
+        assert_eq!(lp_pool.lp_token_supply, MINIMUM_LIQUIDITY);
+        assert!(lp_pool.token_reserve > 0 && lp_pool.staked_token_reserve > 0);
+        assert_eq!(lp_pool.check_invariants(), Ok(()));
+
+        let lp_pool = LpPool::init_with_reserves(config, 10, 5, 20).unwrap();
+        assert_eq!(lp_pool.locked_lp_supply, 20);
+
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 1000, 500, 2000).unwrap();
+        assert_eq!(lp_pool.locked_lp_supply, 0);
+    }
+
+    #[test]
+    fn test_withdraw_fee_defaults_to_zero() {
+        let config = PoolConfig::new(100, 1, 2, 1000);
+        assert_eq!(config.withdraw_fee, 0);
+
+        let mut lp_pool = LpPool::init_with_reserves(config, 1000, 500, 1000).unwrap();
+        assert_eq!(lp_pool.remove_liquidity(500), Ok((500, 250)));
+    }
+
+    #[test]
+    fn test_withdraw_fee_above_100_rejected() {
+        let mut config = PoolConfig::new(100, 1, 2, 1000);
+        config.withdraw_fee = 101;
+        assert_eq!(LpPool::init(config), Err(Errors::FeeTooLarge));
+    }
+
+    #[test]
+    fn test_withdraw_one_unit_short_errors_cleanly() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 200, 300, 500).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.withdraw(500, 201, 300), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.withdraw(500, 200, 301), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool.withdraw(501, 200, 300), Err(Errors::InsufficientLiquidity));
+        assert_eq!(lp_pool, before);
+
+        assert_eq!(lp_pool.withdraw(500, 200, 300), Ok((200, 300)));
+        assert_eq!(lp_pool.token_reserve, 0);
+        assert_eq!(lp_pool.staked_token_reserve, 0);
+    }
+
+    // rounding
+
+    fn pool_with_rounding(rounding: RoundingMode, token_reserve: u64, staked_token_reserve: u64, lp_token_supply: u64) -> LpPool {
+        let mut config = PoolConfig::new(100, 1, 2, 1000);
+        config.rounding = rounding;
+        LpPool::init_with_reserves(config, token_reserve, staked_token_reserve, lp_token_supply).unwrap()
+    }
+
+    #[test]
+    fn test_rounding_defaults_to_floor() {
+        assert_eq!(PoolConfig::new(100, 1, 2, 1000).rounding, RoundingMode::Floor);
+    }
+
+    #[test]
+    fn test_remove_liquidity_rounding_modes() {
+        assert_eq!(pool_with_rounding(RoundingMode::Floor, 10, 5, 3).remove_liquidity(1), Ok((3, 1)));
+        assert_eq!(pool_with_rounding(RoundingMode::Ceil, 10, 5, 3).remove_liquidity(1), Ok((4, 2)));
+        assert_eq!(pool_with_rounding(RoundingMode::Nearest, 10, 5, 3).remove_liquidity(1), Ok((3, 2)));
+    }
+
+    #[test]
+    fn test_add_liquidity_rounding_modes() {
+        assert_eq!(pool_with_rounding(RoundingMode::Floor, 4, 0, 3).add_liquidity(1), Err(Errors::DepositMintsNothing));
+        assert_eq!(pool_with_rounding(RoundingMode::Ceil, 4, 0, 3).add_liquidity(1), Ok(1));
+        assert_eq!(pool_with_rounding(RoundingMode::Nearest, 4, 0, 3).add_liquidity(1), Ok(1));
+    }
+
+    #[test]
+    fn test_ceil_rounding_never_exceeds_reserves() {
+        let mut lp_pool = pool_with_rounding(RoundingMode::Ceil, 10, 5, 3);
+        assert_eq!(lp_pool.remove_liquidity(3), Ok((10, 5)));
+        assert_eq!(lp_pool.token_reserve, 0);
+        assert_eq!(lp_pool.staked_token_reserve, 0);
+    }
+
+    // quote_remove_liquidity
+
+    #[test]
+    fn test_quote_remove_liquidity_matches_withdrawal() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 200;
+        lp_pool.staked_token_reserve = 300;
+        lp_pool.lp_token_supply = 500;
+        let before = lp_pool.clone();
+
+        let quote = lp_pool.quote_remove_liquidity(100).unwrap();
+        assert_eq!(lp_pool, before);
+
+        let result = lp_pool.remove_liquidity(100).unwrap();
+        assert_eq!(quote, result);
+    }
+
+    #[test]
+    fn test_quote_remove_liquidity_zero_amount() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.quote_remove_liquidity(0), Err(Errors::PropertyMustBeGreaterThanZero));
+    }
+
+    #[test]
+    fn test_quote_remove_liquidity_insufficient() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.lp_token_supply = 500;
+        assert_eq!(lp_pool.quote_remove_liquidity(600), Err(Errors::InsufficientLiquidity));
+    }
+
+    // lp_to_token_value
+
+    #[test]
+    fn test_lp_to_token_value_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.lp_to_token_value(100), Err(Errors::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_lp_to_token_value_matches_remove_liquidity() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 200;
+        lp_pool.staked_token_reserve = 300;
+        lp_pool.lp_token_supply = 500;
+
+        let token_value = lp_pool.lp_to_token_value(100).unwrap();
+        assert_eq!(token_value, 40);
+        assert_eq!(lp_pool.remove_liquidity(100).unwrap().0, token_value);
+    }
+
+    #[test]
+    fn test_lp_to_token_value_large_values() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = u64::MAX;
+        lp_pool.lp_token_supply = u64::MAX;
+        assert_eq!(lp_pool.lp_to_token_value(u64::MAX / 2), Ok(u64::MAX / 2));
+    }
+
+    // lp_tokens_for_token_amount
+
+    #[test]
+    fn test_lp_tokens_for_token_amount_yields_target() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 700, 300, 3000).unwrap();
+
+        let lp_token_amount = lp_pool.lp_tokens_for_token_amount(50).unwrap();
+        assert_eq!(lp_token_amount, 215);
+        assert_eq!(lp_pool.quote_remove_liquidity(lp_token_amount - 1).unwrap().0, 49);
+        assert!(lp_pool.remove_liquidity(lp_token_amount).unwrap().0 >= 50);
+    }
+
+    #[test]
+    fn test_lp_tokens_for_token_amount_with_withdraw_fee() {
+        let mut config = PoolConfig::new(100, 1, 2, 1000);
+        config.withdraw_fee = 2;
+        let mut lp_pool = LpPool::init_with_reserves(config, 700, 300, 3000).unwrap();
+
+        let lp_token_amount = lp_pool.lp_tokens_for_token_amount(50).unwrap();
+        assert!(lp_pool.remove_liquidity(lp_token_amount).unwrap().0 >= 50);
+    }
+
+    #[test]
+    fn test_lp_tokens_for_token_amount_overflow() {
+        let mut config = PoolConfig::new(100, 1, 2, 1000);
+        config.withdraw_fee = 50;
+        let lp_pool = LpPool::init_with_reserves(config, 1000, 300, u64::MAX).unwrap();
+
+        assert_eq!(lp_pool.lp_tokens_for_token_amount(1), Ok(u64::MAX.div_ceil(500)));
+        assert_eq!(lp_pool.lp_tokens_for_token_amount(600), Err(Errors::Overflow));
+        assert_eq!(lp_pool.lp_tokens_for_token_amount(u64::MAX), Err(Errors::Overflow));
+    }
+
+    #[test]
+    fn test_lp_tokens_for_token_amount_errors() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 700, 300, 3000).unwrap();
+        assert_eq!(lp_pool.lp_tokens_for_token_amount(0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.lp_tokens_for_token_amount(701), Err(Errors::InsufficientLiquidity));
+
+        let empty_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(empty_pool.lp_tokens_for_token_amount(50), Err(Errors::InsufficientLiquidity));
+    }
+
+    // lp_tokens_for_staked_amount
+
+    #[test]
+    fn test_lp_tokens_for_staked_amount_yields_target() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 700, 300, 3000).unwrap();
+
+        let lp_token_amount = lp_pool.lp_tokens_for_staked_amount(50).unwrap();
+        assert_eq!(lp_token_amount, 500);
+        assert_eq!(lp_pool.quote_remove_liquidity(lp_token_amount - 1).unwrap().1, 49);
+        assert!(lp_pool.remove_liquidity(lp_token_amount).unwrap().1 >= 50);
+    }
+
+    #[test]
+    fn test_lp_tokens_for_staked_amount_errors() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 700, 0, 3000).unwrap();
+        assert_eq!(lp_pool.lp_tokens_for_staked_amount(0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(lp_pool.lp_tokens_for_staked_amount(1), Err(Errors::InsufficientLiquidity));
+    }
+
+    // swap
+
+    #[test]
+    fn test_swap_fee_rounds_to_nearest() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 5, 1000), 750, 0, 750).unwrap();
+        assert_eq!(lp_pool.calculate_fee_percentage(), 4);
+
+        assert_eq!(lp_pool.swap(40), Ok(40 - 2));
+        assert_eq!(lp_pool.collected_fees(), 2);
+        assert_eq!(swap_fee_amount(40, 4), 2);
+        assert_eq!(swap_fee_amount(30, 4), 1);
+        assert_eq!(fee_amount(40, 4), 1);
+    }
+
+    #[test]
+    fn test_swap_min_amount() {
+        let mut config = PoolConfig::new(10, 1, 5, 1000);
+        config.min_swap_amount = Some(10);
+        let mut lp_pool = LpPool::init_with_reserves(config, 1000, 0, 1000).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap(9), Err(Errors::SwapTooSmall));
+        assert_eq!(lp_pool, before);
+        assert_eq!(lp_pool.swap(10), Ok(95));
+        assert_eq!(lp_pool.token_reserve, 900);
+    }
+
+    #[test]
+    fn test_swap_min_amount_applies_to_every_variant() {
+        let mut config = PoolConfig::new(10, 1, 5, 1000);
+        config.min_swap_amount = Some(10);
+        let mut lp_pool = LpPool::init_with_reserves(config, 1000, 0, 1000).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.swap_no_fee(9), Err(Errors::SwapTooSmall));
+        assert_eq!(lp_pool.swap_with_fee_override(9, 100), Err(Errors::SwapTooSmall));
+        assert_eq!(lp_pool.swap_tiered(9, &[(0, 1)]), Err(Errors::SwapTooSmall));
+        assert_eq!(lp_pool.swap_exact_out(50), Err(Errors::SwapTooSmall));
+        assert_eq!(lp_pool.swap_dynamic_fee(9), Err(Errors::SwapTooSmall));
+        assert_eq!(lp_pool.swap_saturating(9), Err(Errors::SwapTooSmall));
+        assert_eq!(lp_pool, before);
+
+        assert_eq!(lp_pool.swap_no_fee(10), Ok(100));
+    }
+
+    #[test]
+    fn test_swap_respects_reserve_floor() {
+        let mut config = PoolConfig::new(10, 1, 5, 1000);
+        config.reserve_floor = 800;
+        let mut lp_pool = LpPool::init_with_reserves(config, 1000, 0, 1000).unwrap();
+
+        assert_eq!(lp_pool.swap(20), Ok(190));
+        assert_eq!(lp_pool.token_reserve, 800);
+
+        let before = lp_pool.clone();
+        assert_eq!(lp_pool.swap(1), Err(Errors::ReserveFloorBreached));
+        assert_eq!(lp_pool.swap_saturating(1), Err(Errors::ReserveFloorBreached));
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_swap_with_sufficient_liquidity() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        lp_pool.staked_token_reserve = 100;
+        
+        let result = lp_pool.swap(10).unwrap();
+        assert_eq!(result, 980);
+    }
+
+    #[test]
+    fn test_swap_with_insufficient_liquidity() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+
+        let _ = lp_pool.add_liquidity(1050);
+        let result = lp_pool.swap(11);
+        assert_eq!(result, Err(Errors::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_swap_with_zero_provided() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        
+        let result = lp_pool.swap(0);
+        assert_eq!(result, Err(Errors::PropertyMustBeGreaterThanZero));
+    }
+
+    #[test]
+    fn test_swap_with_zero_token_reserve() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        let result = lp_pool.swap(10);
+        assert_eq!(result, Err(Errors::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_swap_with_fee_and_liquidity_ratio() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        lp_pool.staked_token_reserve = 100;
+        lp_pool.lp_token_supply = 1000;
+        
+        let result = lp_pool.swap(10).unwrap();
+        assert_eq!(result, 980);
+    }
+
+    // total_value_locked
+
+    #[test]
+    fn test_total_value_locked() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 500;
+        lp_pool.staked_token_reserve = 40;
+        assert_eq!(lp_pool.total_value_locked(), 900);
+    }
+
+    #[test]
+    fn test_total_value_locked_does_not_overflow() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(u64::MAX, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = u64::MAX;
+        lp_pool.staked_token_reserve = u64::MAX;
+        assert_eq!(lp_pool.total_value_locked(), u64::MAX as u128 + u64::MAX as u128 * u64::MAX as u128);
+    }
+
+    // token_to_staked / staked_to_token
+
+    #[test]
+    fn test_token_staked_conversion_round_trip() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.staked_to_token(25), Ok(250));
+        assert_eq!(lp_pool.token_to_staked(250), 25);
+        assert_eq!(lp_pool.token_to_staked(259), 25);
+    }
+
+    #[test]
+    fn test_staked_to_token_overflow() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.staked_to_token(u64::MAX / 5), Err(Errors::Overflow));
+    }
+
+    // to_display / from_display
+
+    #[test]
+    fn test_display_round_trip_6_decimals() {
+        let mut config = PoolConfig::new(10, 1, 2, 1000);
+        config.decimals = 6;
+        let lp_pool = LpPool::init(config).unwrap();
+
+        assert_eq!(lp_pool.from_display(1.5), 1_500_000);
+        assert_eq!(lp_pool.to_display(1_500_000), 1.5);
+        assert_eq!(lp_pool.from_display(lp_pool.to_display(1_234_567_891)), 1_234_567_891);
+        assert_eq!(lp_pool.to_display(lp_pool.from_display(1234.567891)), 1234.567891);
+    }
+
+    #[test]
+    fn test_display_round_trip_9_decimals() {
+        let mut config = PoolConfig::new(10, 1, 2, 1000);
+        config.decimals = 9;
+        let lp_pool = LpPool::init(config).unwrap();
+
+        assert_eq!(lp_pool.from_display(0.000000001), 1);
+        assert_eq!(lp_pool.from_display(lp_pool.to_display(987_654_321_012)), 987_654_321_012);
+        assert_eq!(lp_pool.to_display(lp_pool.from_display(42.123456789)), 42.123456789);
+    }
+
+    #[test]
+    fn test_display_zero_decimals_is_identity() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.to_display(1234), 1234.0);
+        assert_eq!(lp_pool.from_display(1234.0), 1234);
+        assert_eq!(lp_pool.from_display(-1.0), 0);
+    }
+
+    // diff
+
+    #[test]
+    fn test_diff_reports_swap_changes() {
+        let before = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let mut after = before.clone();
+        after.swap(10).unwrap();
+
+        assert_eq!(before.diff(&after), vec![
+            ("token_reserve", 1000, 900),
+            ("staked_token_reserve", 0, 10),
+            ("total_fees", 0, 5),
+            ("total_gross_swapped", 0, 100),
+            ("collected_fees", 0, 5),
+            ("treasury_balance", 0, 5),
+            ("swaps", 0, 1),
+            ("sequence", 0, 1),
+        ]);
+        assert!(before.diff(&before.clone()).is_empty());
+    }
+
+    #[test]
+    fn test_diff_reports_settings_and_price_history() {
+        let before = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let mut after = before.clone();
+        after.config.rounding = RoundingMode::Nearest;
+        after.config.max_lp_supply = Some(5000);
+        after.config.reserve_floor = 100;
+        after.config.min_swap_amount = Some(3);
+        after.config.overflow_policy = OverflowPolicy::Saturating;
+        after.update_price(12).unwrap();
+        after.set_paused(true);
+        after.set_dry_run(true);
+        after.sequence = 7;
+
+        assert_eq!(before.diff(&after), vec![
+            ("price", 10, 12),
+            ("rounding", 0, 2),
+            ("max_lp_supply", u64::MAX, 5000),
+            ("reserve_floor", 0, 100),
+            ("min_swap_amount", 0, 3),
+            ("overflow_policy", 0, 1),
+            ("sequence", 0, 7),
+            ("dry_run", 0, 1),
+            ("paused", 0, 1),
+            ("price_history", 0, 12),
+        ]);
+    }
+
+    // pool_metrics
+
+    #[test]
+    fn test_pool_metrics_match_accessors() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        lp_pool.swap(10).unwrap();
+        lp_pool.swap(20).unwrap();
+
+        let metrics = lp_pool.pool_metrics();
+        assert_eq!(metrics.tvl, lp_pool.total_value_locked());
+        assert_eq!(metrics.lp_token_price, lp_pool.lp_token_price());
+        assert_eq!(metrics.fee_bps, lp_pool.current_fee_bps());
+        assert_eq!(metrics.reserve_ratio, lp_pool.utilization());
+        assert_eq!(metrics.solvent, lp_pool.is_solvent());
+        assert_eq!(metrics.swap_count, lp_pool.stats().swaps);
+        assert_eq!(metrics.total_volume, lp_pool.total_volume());
+        assert_eq!(metrics.accrued_fees, lp_pool.collected_fees());
+
+        assert_eq!(metrics.swap_count, 2);
+        assert_eq!(metrics.total_volume, 300);
+    }
+
+    // metrics
+
+    #[test]
+    fn test_metrics_key_values() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 500, 30, 800).unwrap();
+        let metrics = lp_pool.metrics();
+        let metric = |name| metrics.iter().find(|&&(key, _)| key == name).map(|&(_, value)| value);
+
+        assert_eq!(metric("token_reserve"), Some(500));
+        assert_eq!(metric("fee_percentage"), Some(3));
+        assert_eq!(metric("tvl"), Some(800));
+        assert_eq!(metric("lp_token_supply"), Some(800));
+        assert_eq!(metric("unknown"), None);
+    }
+
+    #[test]
+    fn test_metrics_tvl_clamped() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(u64::MAX, 1, 2, 1000), 0, u64::MAX, 1).unwrap();
+        assert!(lp_pool.metrics().contains(&("tvl", u64::MAX)));
+    }
+
+    // value_cmp
+
+    #[test]
+    fn test_value_cmp_sorts_pools_by_value() {
+        let config = PoolConfig::new(10, 1, 2, 1000);
+        let small = LpPool::init_with_reserves(config, 500, 0, 500).unwrap();
+        let medium = LpPool::init_with_reserves(config, 100, 50, 600).unwrap();
+        let large = LpPool::init_with_reserves(PoolConfig::new(u64::MAX, 1, 2, 1000), 0, u64::MAX, 1).unwrap();
+
+        let mut pools = vec![large.clone(), small.clone(), medium.clone()];
+        pools.sort_by(|a, b| a.value_cmp(b));
+        assert_eq!(pools, vec![small, medium, large]);
+    }
+
+    #[test]
+    fn test_value_cmp_equal_value() {
+        let config = PoolConfig::new(10, 1, 2, 1000);
+        let tokens_only = LpPool::init_with_reserves(config, 600, 0, 600).unwrap();
+        let mixed = LpPool::init_with_reserves(config, 100, 50, 600).unwrap();
+        assert_eq!(tokens_only.value_cmp(&mixed), core::cmp::Ordering::Equal);
+    }
+
+    // swap_bounds / add_bounds
+
+    #[test]
+    fn test_swap_bounds() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.swap_bounds(), (1, 100));
+        assert!(lp_pool.can_swap(100) && !lp_pool.can_swap(101));
+
+        lp_pool.config.min_swap_amount = Some(5);
+        lp_pool.config.reserve_floor = 401;
+        assert_eq!(lp_pool.swap_bounds(), (5, lp_pool.max_swap_in()));
+        assert_eq!(lp_pool.max_swap_in(), 59);
+        assert!(lp_pool.can_swap(59) && !lp_pool.can_swap(60));
+    }
+
+    #[test]
+    fn test_swap_bounds_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.swap_bounds(), (1, 0));
+    }
+
+    #[test]
+    fn test_swap_bounds_skip_swaps_whose_fee_takes_everything() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 50, 60, 1000), 1000, 0, 1000).unwrap();
+        lp_pool.token_reserve = 10;
+        assert_eq!(lp_pool.calculate_fee_percentage(), 50);
+
+        assert!(!lp_pool.can_swap(1));
+        assert!(lp_pool.can_swap(2));
+        assert_eq!(lp_pool.max_swap_in(), 10);
+        assert_eq!(lp_pool.swap_bounds(), (2, 10));
+    }
+
+    #[test]
+    fn test_add_bounds() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.add_bounds(), (MINIMUM_LIQUIDITY + 1, u64::MAX));
+
+        let mut config = PoolConfig::new(10, 1, 5, 1000);
+        config.max_lp_supply = Some(1500);
+        let lp_pool = LpPool::init_with_reserves(config, 2000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.add_bounds(), (2, 1001));
+        assert_eq!(lp_pool.simulation().add_liquidity(1), Err(Errors::DepositMintsNothing));
+        assert_eq!(lp_pool.simulation().add_liquidity(2), Ok(1));
+        assert_eq!(lp_pool.simulation().add_liquidity(1001), Ok(500));
+        assert_eq!(lp_pool.simulation().add_liquidity(1002), Err(Errors::SupplyCapExceeded));
+    }
+
+    // composition
+
+    #[test]
+    fn test_composition() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 300, 70, 1000).unwrap();
+        assert_eq!(lp_pool.composition(), (30, 70));
+
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 1, 2, 1000).unwrap();
+        assert_eq!(lp_pool.composition(), (4, 96));
+    }
+
+    #[test]
+    fn test_composition_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.composition(), (0, 0));
+    }
+
+    // rebalance_to_ratio
+
+    #[test]
+    fn test_rebalance_to_ratio_50_50_to_70_30() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 1000, 100, 2000).unwrap();
+        assert_eq!(lp_pool.composition(), (50, 50));
+
+        assert_eq!(lp_pool.rebalance_to_ratio(70), Ok(40));
+        assert_eq!((lp_pool.token_reserve, lp_pool.staked_token_reserve), (1400, 60));
+        assert_eq!(lp_pool.composition(), (70, 30));
+        assert_eq!(lp_pool.total_value_locked(), 2000);
+
+        assert_eq!(lp_pool.rebalance_to_ratio(50), Ok(40));
+        assert_eq!((lp_pool.token_reserve, lp_pool.staked_token_reserve), (1000, 100));
+    }
+
+    #[test]
+    fn test_rebalance_to_ratio_impossible_targets() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 1000, 100, 2000).unwrap();
+        assert_eq!(lp_pool.rebalance_to_ratio(101), Err(Errors::PercentOutOfRange));
+
+        lp_pool.config.reserve_floor = 500;
+        assert_eq!(lp_pool.rebalance_to_ratio(0), Err(Errors::ReserveFloorBreached));
+        assert_eq!((lp_pool.token_reserve, lp_pool.staked_token_reserve), (1000, 100));
+
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.rebalance_to_ratio(70), Err(Errors::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_rebalance_to_ratio_overflow() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(u64::MAX, 1, 2, 1000), u64::MAX, u64::MAX, 1).unwrap();
+        let before = lp_pool.clone();
+
+        assert_eq!(lp_pool.rebalance_to_ratio(50), Err(Errors::Overflow));
+        assert_eq!(lp_pool, before);
+    }
+
+    // product_invariant
+
+    #[test]
+    fn test_product_invariant_known_pool_and_swap() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 1000, 100, 2000).unwrap();
+        assert_eq!(lp_pool.product_invariant(), Some(100_000));
+
+        lp_pool.swap(10).unwrap();
+        assert_eq!((lp_pool.token_reserve, lp_pool.staked_token_reserve), (900, 110));
+        assert_eq!(lp_pool.product_invariant(), Some(900 * 110));
+    }
+
+    #[test]
+    fn test_product_invariant_empty_side_and_extremes() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.product_invariant(), None);
+
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(u64::MAX, 1, 2, 1000), u64::MAX, u64::MAX, 1).unwrap();
+        assert_eq!(lp_pool.product_invariant(), Some(u64::MAX as u128 * u64::MAX as u128));
+    }
+
+    // max_removable_lp
+
+    #[test]
+    fn test_max_removable_lp_boundary() {
+        let mut lp_pool = pool_with_rounding(RoundingMode::Ceil, 8, 4, 11);
+        lp_pool.config.price = 1;
+        lp_pool.locked_lp_supply = 1;
+        assert!(lp_pool.is_solvent());
+
+        let max_removable_lp = lp_pool.max_removable_lp();
+        assert_eq!(max_removable_lp, 5);
+
+        let mut removed = lp_pool.clone();
+        removed.remove_liquidity(max_removable_lp).unwrap();
+        assert!(removed.is_solvent());
+
+        let mut one_more = lp_pool.clone();
+        one_more.remove_liquidity(max_removable_lp + 1).unwrap();
+        assert!(!one_more.is_solvent());
+    }
+
+    #[test]
+    fn test_max_removable_lp_floor_releases_everything() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.add_liquidity(2000).unwrap();
+        lp_pool.swap(10).unwrap();
+        assert_eq!(lp_pool.max_removable_lp(), 2000 - MINIMUM_LIQUIDITY);
+    }
+
+    #[test]
+    fn test_max_removable_lp_insolvent_pool() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 500, 0, 1000).unwrap();
+        assert_eq!(lp_pool.max_removable_lp(), 0);
+    }
+
+    // is_solvent
+
+    #[test]
+    fn test_is_solvent_healthy_pool() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert!(lp_pool.is_solvent());
+
+        lp_pool.add_liquidity(2000).unwrap();
+        lp_pool.swap(50).unwrap();
+        assert!(lp_pool.is_solvent());
+    }
+
+    #[test]
+    fn test_is_solvent_undercollateralized_pool() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 500;
+        lp_pool.staked_token_reserve = 40;
+        lp_pool.lp_token_supply = 901;
+        assert!(!lp_pool.is_solvent());
+
+        lp_pool.lp_token_supply = 900;
+        assert!(lp_pool.is_solvent());
+    }
+
+    #[test]
+    fn test_min_safe_price() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 500, 40, 900).unwrap();
+        assert_eq!(lp_pool.min_safe_price(), 10);
+        assert!(lp_pool.is_solvent());
+
+        lp_pool.config.price = lp_pool.min_safe_price() - 1;
+        assert!(!lp_pool.is_solvent());
+
+        lp_pool.lp_token_supply = 901;
+        assert_eq!(lp_pool.min_safe_price(), 11);
+    }
+
+    #[test]
+    fn test_min_safe_price_edges() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 1000, 40, 900).unwrap();
+        assert_eq!(lp_pool.min_safe_price(), 1);
+
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 500, 0, 900).unwrap();
+        assert_eq!(lp_pool.min_safe_price(), u64::MAX);
+    }
+
+    #[test]
+    fn test_is_solvent_does_not_overflow() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(u64::MAX, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = u64::MAX;
+        lp_pool.staked_token_reserve = u64::MAX;
+        lp_pool.lp_token_supply = u64::MAX;
+        assert!(lp_pool.is_solvent());
+    }
+
+    // lp_token_price
+
+    #[test]
+    fn test_lp_token_price() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.lp_token_price(), None);
+
+        lp_pool.token_reserve = 500;
+        lp_pool.staked_token_reserve = 40;
+        lp_pool.lp_token_supply = 600;
+        assert_eq!(lp_pool.lp_token_price(), Some(1.5));
+    }
+
+    // lp_value_in_staked
+
+    #[test]
+    fn test_lp_value_in_staked_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.lp_value_in_staked(), None);
+    }
+
+    #[test]
+    fn test_lp_value_in_staked_funded_pool() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 500, 40, 600).unwrap();
+        assert_eq!(lp_pool.lp_token_price(), Some(1.5));
+        assert_eq!(lp_pool.lp_value_in_staked(), Some(0.15));
+    }
+
+    // mint_rate
+
+    #[test]
+    fn test_mint_rate_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.mint_rate(), None);
+    }
+
+    #[test]
+    fn test_mint_rate_funded_pool() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 2000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.mint_rate(), Some(0.5));
+        assert_eq!(lp_pool.lp_to_token_value(1000), Ok(2000));
+
+        lp_pool.token_reserve = 500;
+        assert_eq!(lp_pool.mint_rate(), Some(2.0));
+    }
+
+    // lp_share_percent
+
+    #[test]
+    fn test_lp_share_percent_half_and_full() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 2000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.lp_share_percent(500), Some(50.0));
+        assert_eq!(lp_pool.lp_share_percent(1000), Some(100.0));
+    }
+
+    #[test]
+    fn test_lp_share_percent_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.lp_share_percent(500), None);
+    }
+
+    // lp_price_sensitivity
+
+    #[test]
+    fn test_lp_price_sensitivity_token_heavy() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 9000, 10, 10000).unwrap();
+        assert_eq!(lp_pool.lp_price_sensitivity(1000), Ok(100));
+    }
+
+    #[test]
+    fn test_lp_price_sensitivity_staked_heavy() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(100, 1, 2, 1000), 1000, 90, 10000).unwrap();
+        assert_eq!(lp_pool.lp_price_sensitivity(1000), Ok(900));
+    }
+
+    #[test]
+    fn test_lp_price_sensitivity_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(100, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.lp_price_sensitivity(1000), Ok(0));
+    }
+
+    #[test]
+    fn test_lp_price_sensitivity_overflow() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(u64::MAX / 2, 1, 2, 1000), 1000, 10, 10000).unwrap();
+        assert_eq!(lp_pool.lp_price_sensitivity(10_000), Ok(9_999));
+        assert_eq!(lp_pool.lp_price_sensitivity(10_001), Err(Errors::Overflow));
+        assert_eq!(lp_pool.lp_price_sensitivity(u64::MAX), Err(Errors::Overflow));
+
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 2, 1000), 1, 10, 10000).unwrap();
+        assert_eq!(lp_pool.lp_price_sensitivity(u64::MAX - 10_000), Err(Errors::Overflow));
+    }
+
+    // lp_vs_hold_bps
+
+    #[test]
+    fn test_lp_vs_hold_fee_dominant() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1100;
+        lp_pool.lp_token_supply = 1000;
+        assert_eq!(lp_pool.lp_vs_hold_bps(1000, 0, 1000), Ok(1000));
+    }
+
+    #[test]
+    fn test_lp_vs_hold_divergence_dominant() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 500;
+        lp_pool.staked_token_reserve = 40;
+        lp_pool.lp_token_supply = 1000;
+        assert_eq!(lp_pool.lp_vs_hold_bps(1000, 0, 1000), Ok(-1000));
+    }
+
+    #[test]
+    fn test_lp_vs_hold_nothing_deposited() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.lp_vs_hold_bps(0, 0, 0), Ok(0));
+    }
+
+    #[test]
+    fn test_lp_vs_hold_overflow() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(u64::MAX, 1, 2, 1000), u64::MAX, u64::MAX, u64::MAX).unwrap();
+        assert_eq!(lp_pool.lp_vs_hold_bps(u64::MAX, u64::MAX, u64::MAX), Err(Errors::Overflow));
+        assert_eq!(lp_pool.lp_vs_hold_bps(1, 0, u64::MAX), Err(Errors::Overflow));
+
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(u64::MAX, 1, 2, 1000), u64::MAX, u64::MAX, 1).unwrap();
+        assert_eq!(lp_pool.lp_vs_hold_bps(1, 0, 2), Err(Errors::Overflow));
+
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(u64::MAX, 1, 2, 1000), 0, 1 << 62, 1 << 62).unwrap();
+        assert_eq!(lp_pool.lp_vs_hold_bps(0, 1, 1), Ok(0));
+        assert_eq!(lp_pool.lp_vs_hold_bps(1, 0, 1), Err(Errors::Overflow));
+    }
+
+    // capacity_at_slippage
+
+    #[test]
+    fn test_capacity_grows_with_slippage_tolerance() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(1, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+
+        assert_eq!(lp_pool.capacity_at_slippage(100), Ok(24));
+        assert_eq!(lp_pool.capacity_at_slippage(150), Ok(74));
+        assert_eq!(lp_pool.capacity_at_slippage(200), Ok(1000));
+    }
+
+    #[test]
+    fn test_capacity_limited_by_reserve() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        assert_eq!(lp_pool.capacity_at_slippage(10_000), Ok(100));
+    }
+
+    #[test]
+    fn test_capacity_uses_oracle_price() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        lp_pool.set_price_oracle(Box::new(FixedPrice(20)));
+        assert_eq!(lp_pool.capacity_at_slippage(10_000), Ok(50));
+        assert!(lp_pool.can_swap(50));
+    }
+
+    #[test]
+    fn test_capacity_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.capacity_at_slippage(10_000), Err(Errors::InsufficientLiquidity));
+    }
+
+    // min_profitable_swap
+
+    #[test]
+    fn test_min_profitable_swap() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.min_profitable_swap(8), Ok(1));
+        assert_eq!(lp_pool.min_profitable_swap(9), Ok(2));
+    }
+
+    #[test]
+    fn test_min_profitable_swap_uses_oracle_price() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.min_profitable_swap(18), Err(Errors::NotProfitable));
+
+        lp_pool.set_price_oracle(Box::new(FixedPrice(20)));
+        assert_eq!(lp_pool.min_profitable_swap(18), Ok(1));
+    }
+
+    #[test]
+    fn test_min_profitable_swap_unprofitable() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.min_profitable_swap(10), Err(Errors::NotProfitable));
+
+        let empty_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(empty_pool.min_profitable_swap(1), Err(Errors::InsufficientLiquidity));
+    }
+
+    // swaps_until_fee_band_change
+
+    #[test]
+    fn test_swaps_until_fee_band_change() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(1, 1, 3, 1000)).unwrap();
+        lp_pool.token_reserve = 990;
+        assert_eq!(lp_pool.calculate_fee_percentage(), 2);
+
+        assert_eq!(lp_pool.swaps_until_fee_band_change(100), Ok(5));
+        assert_eq!(lp_pool.swaps_until_fee_band_change(250), Ok(2));
+        assert_eq!(lp_pool.token_reserve, 990);
+    }
+
+    #[test]
+    fn test_swaps_until_fee_band_change_first_swap() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(1, 1, 3, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        assert_eq!(lp_pool.swaps_until_fee_band_change(10), Ok(1));
+    }
+
+    #[test]
+    fn test_swaps_until_fee_band_change_runs_dry() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(1, 1, 3, 1000)).unwrap();
+        lp_pool.token_reserve = 990;
+        assert_eq!(lp_pool.swaps_until_fee_band_change(1000), Err(Errors::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_swaps_until_fee_band_change_matches_swapping_one_by_one() {
+        for (token_reserve, per_swap, reserve_floor) in [(990, 1, 0), (990, 7, 0), (5000, 33, 0), (990, 3, 900), (2_500, 40, 0)] {
+            let mut config = PoolConfig::new(1, 1, 9, 1000);
+            config.reserve_floor = reserve_floor;
+            let lp_pool = LpPool::init_with_reserves(config, token_reserve, 0, 1000).unwrap();
+
+            let mut simulated = lp_pool.clone();
+            let mut expected = Ok(0);
+            loop {
+                if let Err(error) = simulated.swap(per_swap) {
+                    expected = Err(error);
+                    break;
+                }
+                expected = expected.map(|swaps| swaps + 1);
+                if simulated.calculate_fee_percentage() != lp_pool.calculate_fee_percentage() {
+                    break;
+                }
+            }
+            assert_eq!(lp_pool.swaps_until_fee_band_change(per_swap), expected, "{} {} {}", token_reserve, per_swap, reserve_floor);
+        }
+    }
+
+    #[test]
+    fn test_swaps_until_fee_band_change_large_reserve() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 3, 1_000_000_000_000), 990_000_000_000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.calculate_fee_percentage(), 2);
+        assert_eq!(lp_pool.swaps_until_fee_band_change(1), Ok(490_000_000_001));
+    }
+
+    // deposit_timing_advice
+
+    #[test]
+    fn test_deposit_timing_after_swap() {
+        // Now 1000 mints 1000 of 2000 LP tokens, half the pool. The swap drains the token
+        // reserve to 500, so afterwards it mints 2000 of 3000, two thirds.
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.simulation().add_liquidity(1000), Ok(1000));
+        let mut after_swap = lp_pool.simulation();
+        after_swap.swap(50).unwrap();
+        assert_eq!(after_swap.add_liquidity(1000), Ok(2000));
+
+        assert_eq!(lp_pool.deposit_timing_advice(1000, 50), Ok(DepositTiming::AfterSwap));
+    }
+
+    #[test]
+    fn test_deposit_timing_now_when_swap_changes_nothing() {
+        // The swap moves the reserve by one unit, which the mint's rounding absorbs: both
+        // deposits buy 1 of 11 LP tokens.
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(1, 1, 2, 1000), 1000, 0, 10).unwrap();
+        assert_eq!(lp_pool.simulation().add_liquidity(100), Ok(1));
+        let mut after_swap = lp_pool.simulation();
+        after_swap.swap(1).unwrap();
+        assert_eq!(after_swap.add_liquidity(100), Ok(1));
+
+        assert_eq!(lp_pool.deposit_timing_advice(100, 1), Ok(DepositTiming::Now));
+    }
+
+    #[test]
+    fn test_deposit_timing_failing_swap() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 2, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.deposit_timing_advice(1000, 101), Err(Errors::InsufficientLiquidity));
+    }
+
+    // reserve_elasticity
+
+    #[test]
+    fn test_reserve_elasticity_is_per_staked_unit() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+
+        // Each staked token drains price = 10 tokens, 1% of the reserve, whatever the swap size.
+        assert_eq!(lp_pool.reserve_elasticity(1), Ok(-100));
+        assert_eq!(lp_pool.reserve_elasticity(10), Ok(-100));
+        assert_eq!(lp_pool.reserve_elasticity(50), Ok(-100));
+        assert_eq!(lp_pool.reserve_elasticity(100), Ok(-100));
+    }
+
+    #[test]
+    fn test_reserve_elasticity_relative_to_reserves() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 10000;
+
+        assert_eq!(lp_pool.reserve_elasticity(50), Ok(-10));
+    }
+
+    #[test]
+    fn test_reserve_elasticity_does_not_mutate() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        let before = lp_pool.clone();
+
+        let _ = lp_pool.reserve_elasticity(10);
+        assert_eq!(lp_pool, before);
+    }
+
+    #[test]
+    fn test_reserve_elasticity_insufficient_liquidity() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 2, 1000)).unwrap();
+        assert_eq!(lp_pool.reserve_elasticity(10), Err(Errors::InsufficientLiquidity));
+    }
+
+    #[test]
+    fn test_reserve_elasticity_overflow() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(1, 1, 2, 1000)).unwrap();
+        lp_pool.token_reserve = u64::MAX;
+
+        assert_eq!(lp_pool.reserve_elasticity(u64::MAX), Err(Errors::Overflow));
+    }
+
+    // volume_to_offset_exit_fee
+
+    fn pool_with_exit_fee(withdraw_fee: u64, protocol_fee_share: u64) -> LpPool {
+        let mut config = PoolConfig::new(10, 1, 5, 1000);
+        config.withdraw_fee = withdraw_fee;
+        config.protocol_fee_share = protocol_fee_share;
+        LpPool::init_with_reserves(config, 1000, 100, 2000).unwrap()
+    }
+
+    #[test]
+    fn test_volume_to_offset_exit_fee_known_rates() {
+        // Burning half the supply withdraws (500, 50) and pays 2% of it, 10 + 1 * 10 = 20 tokens.
+        // The position earns 5% * 50% * 1/2 = 1.25% of every swapped token.
+        let lp_pool = pool_with_exit_fee(2, 50);
+        assert_eq!(lp_pool.calculate_fee_percentage(), 5);
+        assert_eq!(lp_pool.volume_to_offset_exit_fee(1000), Ok(1600));
+
+        // A tenth of the supply withdraws (100, 10); the fee on the staked side truncates to 0,
+        // so only 2 tokens have to be earned back at a tenth of the accrual.
+        assert_eq!(lp_pool.volume_to_offset_exit_fee(200), Ok(800));
+
+        let lp_pool = pool_with_exit_fee(2, 0);
+        assert_eq!(lp_pool.volume_to_offset_exit_fee(1000), Ok(800));
+    }
+
+    #[test]
+    fn test_volume_to_offset_exit_fee_edge_cases() {
+        assert_eq!(pool_with_exit_fee(0, 50).volume_to_offset_exit_fee(1000), Ok(0));
+        assert_eq!(pool_with_exit_fee(2, 100).volume_to_offset_exit_fee(1000), Err(Errors::NoFeeAccrual));
+        assert_eq!(pool_with_exit_fee(2, 50).volume_to_offset_exit_fee(0), Err(Errors::PropertyMustBeGreaterThanZero));
+        assert_eq!(pool_with_exit_fee(2, 50).volume_to_offset_exit_fee(2001), Err(Errors::InsufficientLiquidity));
+    }
+
+    // round_trip_cost
+
+    #[test]
+    fn test_round_trip_cost_small_for_typical_pool() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1_000_000, 0, 1_000_000).unwrap();
+        assert_eq!(lp_pool.round_trip_cost(100), Ok(0));
+        assert_eq!(lp_pool.round_trip_cost(500_000), Ok(0));
+        assert_eq!(lp_pool.token_reserve, 1_000_000);
+    }
+
+    #[test]
+    fn test_round_trip_cost_counts_locked_minimum_on_first_deposit() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.round_trip_cost(5_000), Ok(MINIMUM_LIQUIDITY));
+    }
+
+    #[test]
+    fn test_round_trip_cost_counts_withdraw_fee() {
+        let mut config = PoolConfig::new(10, 1, 5, 1000);
+        config.withdraw_fee = 1;
+        let lp_pool = LpPool::init_with_reserves(config, 1_000_000, 0, 1_000_000).unwrap();
+        assert_eq!(lp_pool.round_trip_cost(1_000), Ok(1 + 9));
+        assert_eq!(lp_pool.round_trip_cost(0), Err(Errors::PropertyMustBeGreaterThanZero));
+    }
+
+    // verify_deposit_withdraw_neutral
+
+    #[test]
+    fn test_verify_deposit_withdraw_neutral_within_tolerance() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1_000_000, 0, 1_000_000).unwrap();
+        for amount in [2, 7, 100, 999, 1_000, 10_000, 500_000, 3_000_000] {
+            let loss = lp_pool.verify_deposit_withdraw_neutral(amount).unwrap();
+            assert!(loss <= 1, "amount {} lost {}", amount, loss);
+        }
+        assert_eq!(lp_pool.token_reserve, 1_000_000);
+    }
+
+    #[test]
+    fn test_verify_deposit_withdraw_neutral_lone_lp_on_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        for amount in [MINIMUM_LIQUIDITY + 1, 5_000, 1_000_000] {
+            assert_eq!(lp_pool.verify_deposit_withdraw_neutral(amount), Ok(0));
+        }
+    }
+
+    #[test]
+    fn test_verify_deposit_withdraw_neutral_too_lossy() {
+        let mut config = PoolConfig::new(10, 1, 5, 1000);
+        config.withdraw_fee = 1;
+        let lp_pool = LpPool::init_with_reserves(config, 1_000_000, 0, 1_000_000).unwrap();
+        assert_eq!(lp_pool.round_trip_cost(10_000), Ok(100));
+        assert_eq!(lp_pool.verify_deposit_withdraw_neutral(10_000), Err(Errors::RoundingLossTooHigh));
+        assert_eq!(lp_pool.verify_deposit_withdraw_neutral(0), Err(Errors::PropertyMustBeGreaterThanZero));
+    }
+
+    // simulate_fee_revenue
+
+    #[test]
+    fn test_simulate_fee_revenue_sums_breakdown_fees() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        let swaps = [20, 30, 25];
+
+        let mut expected = 0;
+        let mut replay = lp_pool.clone();
+        for &staked_token_amount in &swaps {
+            expected += replay.swap_breakdown(staked_token_amount).unwrap().1;
+            replay.swap(staked_token_amount).unwrap();
+        }
+
+        assert_eq!(lp_pool.simulate_fee_revenue(&swaps), Ok(expected));
+        assert_ne!(expected, 750 * lp_pool.calculate_fee_percentage() / 100);
+        assert_eq!(lp_pool.token_reserve, 1000);
+    }
+
+    #[test]
+    fn test_simulate_fee_revenue_failing_swap() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.simulate_fee_revenue(&[]), Ok(0));
+        assert_eq!(lp_pool.simulate_fee_revenue(&[50, 60]), Err(Errors::InsufficientLiquidity));
+    }
+
+    // fee_yield_estimate
+
+    #[test]
+    fn test_fee_yield_estimate() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 100, 1000).unwrap();
+        assert_eq!(lp_pool.calculate_fee_percentage(), 5);
+        assert_eq!(lp_pool.fee_yield_estimate(4000), Ok(200.0 / 2000.0));
+        assert_eq!(lp_pool.fee_yield_estimate(0), Ok(0.0));
+    }
+
+    #[test]
+    fn test_fee_yield_estimate_empty_pool() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.fee_yield_estimate(4000), Err(Errors::InsufficientLiquidity));
+    }
+
+    // break_even_fee_percent
+
+    #[test]
+    fn test_break_even_fee_percent_known_loss_and_volume() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 9, 1000), 1000, 0, 1000).unwrap();
+        lp_pool.total_gross_swapped = 10_000;
+
+        assert_eq!(lp_pool.break_even_fee_percent(300), 3);
+        assert_eq!(lp_pool.break_even_fee_percent(301), 4);
+        assert_eq!(lp_pool.break_even_fee_percent(10), 1);
+        assert_eq!(lp_pool.break_even_fee_percent(5000), 9);
+    }
+
+    #[test]
+    fn test_break_even_fee_percent_without_volume() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 9, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.break_even_fee_percent(300), 9);
+        assert_eq!(lp_pool.break_even_fee_percent(0), 1);
+    }
+
+    // revenue_maximizing_fee_bps
+
+    #[test]
+    fn test_revenue_maximizing_fee_interior() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 9, 1000)).unwrap();
+        assert_eq!(lp_pool.revenue_maximizing_fee_bps(1000, 1_000_000), 500);
+        assert_eq!(lp_pool.revenue_maximizing_fee_bps(2000, 1_000_000), 250);
+    }
+
+    #[test]
+    fn test_revenue_maximizing_fee_at_boundaries() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 9, 1000)).unwrap();
+        assert_eq!(lp_pool.revenue_maximizing_fee_bps(100, 1_000_000), 900);
+        assert_eq!(lp_pool.revenue_maximizing_fee_bps(10_000, 1_000_000), 100);
+        assert_eq!(lp_pool.revenue_maximizing_fee_bps(0, 1_000_000), 900);
+    }
+
+    #[test]
+    fn test_revenue_maximizing_fee_without_volume() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 9, 1000)).unwrap();
+        assert_eq!(lp_pool.revenue_maximizing_fee_bps(1000, 0), 100);
+    }
+
+    // distance_to_target
+
+    #[test]
+    fn test_distance_to_target() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.token_reserve = 1500;
+        assert_eq!(lp_pool.distance_to_target(), 500);
+
+        lp_pool.token_reserve = 1000;
+        assert_eq!(lp_pool.distance_to_target(), 0);
+
+        lp_pool.token_reserve = 250;
+        assert_eq!(lp_pool.distance_to_target(), -750);
+    }
+
+    #[test]
+    fn test_distance_to_target_clamped() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1)).unwrap();
+        lp_pool.token_reserve = u64::MAX;
+        assert_eq!(lp_pool.distance_to_target(), i64::MAX);
+
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, u64::MAX)).unwrap();
+        assert_eq!(lp_pool.distance_to_target(), i64::MIN);
+    }
+
+    // deposit_to_reach_target
+
+    #[test]
+    fn test_deposit_to_reach_target_under_target() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 5000)).unwrap();
+        assert_eq!(lp_pool.deposit_to_reach_target(), 5000);
+
+        lp_pool.add_liquidity(2000).unwrap();
+        assert_eq!(lp_pool.deposit_to_reach_target(), 3000);
+        lp_pool.add_liquidity(lp_pool.deposit_to_reach_target()).unwrap();
+        assert_eq!(lp_pool.utilization(), 100);
+    }
+
+    #[test]
+    fn test_deposit_to_reach_target_over_target() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1500, 0, 1500).unwrap();
+        assert_eq!(lp_pool.deposit_to_reach_target(), 0);
+    }
+
+    // utilization
+
+    #[test]
+    fn test_utilization_below_target() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 2000), 500, 0, 500).unwrap();
+        assert_eq!(lp_pool.utilization(), 25);
+        assert_eq!(lp_pool.calculate_fee_percentage(), 1 + 25 * 4 / 100);
+    }
+
+    #[test]
+    fn test_utilization_at_target() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 2000), 2000, 0, 2000).unwrap();
+        assert_eq!(lp_pool.utilization(), 100);
+        assert_eq!(lp_pool.calculate_fee_percentage(), 5);
+    }
+
+    #[test]
+    fn test_utilization_above_target() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 500), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.utilization(), 200);
+        assert_eq!(lp_pool.calculate_fee_percentage(), 5);
+    }
+
+    #[test]
+    fn test_utilization_clamped_to_u64() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1), u64::MAX, 0, 1).unwrap();
+        assert_eq!(lp_pool.utilization(), u64::MAX);
+    }
+
+    // current_fee_bps
+
+    #[test]
+    fn test_current_fee_bps_tracks_utilization() {
+        let mut lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 500, 0, 500).unwrap();
+        assert_eq!(lp_pool.calculate_fee_percentage(), 3);
+        assert_eq!(lp_pool.current_fee_bps(), 300);
+
+        lp_pool.token_reserve = 1000;
+        assert_eq!(lp_pool.current_fee_bps(), 500);
+        lp_pool.token_reserve = 0;
+        assert_eq!(lp_pool.current_fee_bps(), 500);
+        lp_pool.token_reserve = 100;
+        assert_eq!(lp_pool.current_fee_bps(), 100);
+    }
+
+    // projected_fee
+
+    #[test]
+    fn test_projected_fee_at_min_and_max_utilization() {
+        let lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.projected_fee(10_000, 0), Ok(10_000 / 100));
+        assert_eq!(lp_pool.projected_fee(10_000, 100), Ok(10_000 * 5 / 100));
+        assert_eq!(lp_pool.projected_fee(10_000, 50), Ok(10_000 * 3 / 100));
+    }
+
+    #[test]
+    fn test_projected_fee_ignores_live_reserve() {
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, 1000), 1000, 0, 1000).unwrap();
+        assert_eq!(lp_pool.calculate_fee_percentage(), 5);
+        assert_eq!(lp_pool.projected_fee(10_000, 0), Ok(100));
+    }
+
+    // fee_calculation        
+
+    #[test]
+    fn test_fee_calculation_at_min() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 2000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        lp_pool.staked_token_reserve = 500;
+        lp_pool.lp_token_supply = 100;
+
+        let fee_percentage = lp_pool.calculate_fee_percentage();
+        assert_eq!(fee_percentage, 3);
+    }
+
+    #[test]
+    fn test_fee_calculation_reserve_above_u64_max_over_100() {
+        let token_reserve = u64::MAX / 100 + 1000;
+        let lp_pool = LpPool::init_with_reserves(PoolConfig::new(10, 1, 5, u64::MAX / 50), token_reserve, 0, 1000).unwrap();
+        assert_eq!(lp_pool.utilization(), 50);
+        assert_eq!(lp_pool.calculate_fee_percentage(), 1 + 50 * 4 / 100);
+    }
+
+    #[test]
+    fn test_fee_calculation_equal_fee_min_and_max() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.config.fee_min = 3;
+        lp_pool.config.fee_max = 3;
+        lp_pool.lp_token_supply = 1000;
+
+        for token_reserve in [0, 1, 500, 1000, 5000, u64::MAX] {
+            lp_pool.token_reserve = token_reserve;
+            assert_eq!(lp_pool.calculate_fee_percentage(), 3);
+        }
+        assert_eq!(lp_pool.projected_fee(1000, 50), Ok(30));
+    }
+
+    #[test]
+    fn test_fee_calculation_at_max() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 500)).unwrap();
+        lp_pool.token_reserve = 1000;
+        lp_pool.staked_token_reserve = 500;
+        lp_pool.lp_token_supply = 100;
+
+        let fee_percentage = lp_pool.calculate_fee_percentage();
+        assert_eq!(fee_percentage, 5);
+    }
+
+    #[test]
+    fn test_fee_calculation_below_min() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 5000)).unwrap();
+        lp_pool.token_reserve = 1000;
+        lp_pool.staked_token_reserve = 500;
+        lp_pool.lp_token_supply = 100;
+
+        let fee_percentage = lp_pool.calculate_fee_percentage();
+        assert_eq!(fee_percentage, 1);
+    }
+
+    #[test]
+    fn test_fee_calculation_with_empty_token_reserve_is_fee_max() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        assert_eq!(lp_pool.calculate_fee_percentage(), 5);
+
+        // The curve itself starts at fee_min, so the fee steps down once the reserve is non-empty.
+        lp_pool.token_reserve = 1;
+        assert_eq!(lp_pool.calculate_fee_percentage(), 1);
+        assert_eq!(lp_pool.fee_percentage_at(0), 5);
+    }
+
+    #[test]
+    fn test_fee_calculation_with_zero_reserves() {
+        let mut lp_pool = LpPool::init(PoolConfig::new(10, 1, 5, 1000)).unwrap();
+        lp_pool.token_reserve = 100;
+        lp_pool.staked_token_reserve = 0;
+        lp_pool.lp_token_supply = 0;
+
+        let fee_percentage = lp_pool.calculate_fee_percentage();
+        assert_eq!(fee_percentage, 1);
+    }
+
+}
+
+#[cfg(test)]
+mod proptests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn pool_config() -> impl Strategy<Value = PoolConfig> {
+        (1..1_000u64, 1..50u64, 1..50u64, 1..1_000_000_000u64).prop_map(|(price, fee_min, fee_span, liquidity_target)| {
+            PoolConfig::new(price, fee_min, fee_min + fee_span, liquidity_target)
+        })
+    }
+
+    // Burns are drawn from the same range as deposits, which mint about one LP token per token
+    // deposited, so they land both within and beyond the outstanding supply.
+    fn commands() -> impl Strategy<Value = Vec<Command>> {
+        let command = prop_oneof![
+            (1..1_000_000_000u64).prop_map(Command::AddLiquidity),
+            (1..1_000_000_000u64).prop_map(Command::RemoveLiquidity),
+            (1..1_000_000u64).prop_map(Command::Swap),
+        ];
+        prop::collection::vec(command, 1..50)
+    }
+
+    proptest! {
+        #[test]
+        fn reserves_never_underflow(config in pool_config(), commands in commands()) {
+            let mut lp_pool = LpPool::init(config).unwrap();
+
+            for &command in &commands {
+                let before = lp_pool.clone();
+                if lp_pool.apply(command).is_err() {
+                    prop_assert_eq!(&lp_pool, &before);
+                }
+                prop_assert_eq!(lp_pool.check_invariants(), Ok(()));
+            }
+        }
+
+        #[test]
+        fn lp_token_supply_tracks_deposits(config in pool_config(), commands in commands()) {
+            let mut lp_pool = LpPool::init(config).unwrap();
+            let mut outstanding: u128 = 0;
+
+            for &command in &commands {
+                match (command, lp_pool.apply(command)) {
+                    (_, Ok(CommandResult::AddLiquidity(minted))) => outstanding += minted as u128,
+                    (Command::RemoveLiquidity(burned), Ok(_)) => outstanding -= burned as u128,
+                    _ => {}
+                }
+                prop_assert_eq!(lp_pool.lp_token_supply as u128, outstanding + lp_pool.locked_lp_supply as u128);
+            }
+        }
+
+        #[test]
+        fn fee_within_band(config in pool_config(), commands in commands()) {
+            let mut lp_pool = LpPool::init(config).unwrap();
+
+            for &command in &commands {
+                let _ = lp_pool.apply(command);
+
+                let fee_percentage = lp_pool.calculate_fee_percentage();
+                prop_assert!(fee_percentage >= config.fee_min);
+                prop_assert!(fee_percentage <= config.fee_max);
+            }
+        }
+    }
+}